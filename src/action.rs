@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of custom keyboard shortcuts supported by rofi (`-kb-custom-1..19`).
+pub const MAX_CUSTOM_KBS: usize = 19;
+
+/// Actions bound to the custom keyboard shortcuts
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    Copy,
+    TextMode,
+    ImageMode,
+    BinaryMode,
+    PinnedMode,
+    SnippetsMode,
+    Transform,
+    ConvertColor,
+    Open,
+    Dedupe,
+    Delete,
+    DeletePrevious,
+    DeleteNext,
+    Sort,
+    Pin,
+    Unpin,
+    MoveUp,
+    MoveDown,
+}
+
+impl Action {
+    /// Default description shown in the shortcuts help.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Action::Copy => "Copy entry",
+            Action::TextMode => "Switch to text",
+            Action::ImageMode => "Switch to images",
+            Action::BinaryMode => "Switch to files",
+            Action::PinnedMode => "Switch to pinned entries",
+            Action::SnippetsMode => "Switch to snippets",
+            Action::Transform => "Transform and copy",
+            Action::ConvertColor => "Convert color and copy",
+            Action::Open => "Open entry",
+            Action::Dedupe => "Delete duplicated texts",
+            Action::Delete => "Delete entry",
+            Action::DeletePrevious => "Delete all entries before the selected one",
+            Action::DeleteNext => "Delete all entries after the selected one",
+            Action::Sort => "Cycle sort order",
+            Action::Pin => "Pin entry",
+            Action::Unpin => "Unpin entry",
+            Action::MoveUp => "Move pinned entry up",
+            Action::MoveDown => "Move pinned entry down",
+        }
+    }
+}
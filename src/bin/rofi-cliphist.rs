@@ -1,12 +1,36 @@
-use std::path::PathBuf;
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
 
-use anyhow::bail;
-use clap::Parser;
+use anyhow::{bail, Context};
+use clap::{Parser, Subcommand, ValueEnum};
 use log::{debug, info, Level};
 use roto::{
-    cache, clipboard, cliphist, config,
-    rofi::{self, cliphist_mode::ClipHistMode},
+    action::Action,
+    cache::{self, SimpleCache},
+    classify::Category,
+    clipboard,
+    cliphist::{self, ClipHistEntry},
+    config,
+    history::{
+        self,
+        pinned::{self, Pinned},
+        HistoryBackend,
+    },
+    index::{self, TextIndex},
+    launcher::{self, Launcher},
+    mime, open,
+    retention::{self, Policy},
+    rofi::{
+        self,
+        cliphist_mode::{self, ClipHistMode},
+        RofiEntry,
+    },
+    secret::{self, Secrets},
+    snippet, transform,
 };
+use serde_json::json;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -15,6 +39,14 @@ struct Args {
     #[command(flatten)]
     verbose: clap_verbosity_flag::Verbosity,
 
+    /// Launcher used to display the entries
+    #[clap(short, long, value_enum)]
+    launcher: Option<launcher::Kind>,
+
+    /// Clipboard history the entries are read from
+    #[clap(short, long, value_enum)]
+    backend: Option<history::Kind>,
+
     /// Path to rofi executable
     #[clap(short, long, default_value = "rofi")]
     rofi_path: Option<String>,
@@ -30,10 +62,89 @@ struct Args {
     /// Sets a custom config file
     #[arg(short = 'f', long, value_name = "FILE")]
     config: Option<PathBuf>,
+
+    /// Entry passed back by rofi when running as a script mode
+    #[arg(hide = true, allow_hyphen_values = true)]
+    input: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Cmd>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Cmd {
+    /// Delete the entries breaking the retention rules of the config file
+    Prune {
+        /// Only print the entries that would be deleted
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+    /// List the entries, pinned ones first then the newest, as `id<TAB>label` lines
+    List {
+        /// Print a JSON array instead
+        #[arg(long)]
+        json: bool,
+        /// Only list the entries of this type
+        #[arg(short = 't', long = "type", value_enum)]
+        kind: Option<EntryType>,
+    },
+    /// Print the content of an entry, refusing to print a detected secret
+    Get {
+        id: String,
+        /// Print the entry even if it looks like a secret
+        #[arg(long)]
+        reveal: bool,
+    },
+    /// Copy an entry to the clipboard
+    Copy { id: String },
+    /// Delete entries, pinned ones have to be unpinned first
+    Delete {
+        #[arg(required = true)]
+        ids: Vec<String>,
+    },
+    /// Print the number and size of the entries
+    Stats,
+    /// Manage the thumbnails cache and the text index
+    Cache {
+        #[command(subcommand)]
+        command: CacheCmd,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheCmd {
+    /// Remove the thumbnails and indexed texts of the entries no longer in the history
+    Gc,
+}
+
+/// Types of entries, as filtered by `list`.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum EntryType {
+    Text,
+    Image,
+    File,
+}
+
+impl EntryType {
+    fn of(entry: &ClipHistEntry) -> Self {
+        match entry {
+            ClipHistEntry::Text { .. } => EntryType::Text,
+            ClipHistEntry::Image { .. } => EntryType::Image,
+            ClipHistEntry::Binary { .. } => EntryType::File,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            EntryType::Text => "text",
+            EntryType::Image => "image",
+            EntryType::File => "file",
+        }
+    }
 }
 
 fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+    let mut args = parse_args();
 
     simple_logger::init_with_level(args.verbose.log_level().unwrap_or(Level::Error))?;
 
@@ -58,32 +169,441 @@ fn main() -> anyhow::Result<()> {
         }
     };
 
+    let input = args.input.clone();
+    let command = args.command.take();
+    let config_path = args.config.clone();
     merge_args_into_config(&mut cfg, args);
 
-    let cliphist = cliphist::new(cfg.cliphist.path);
+    let keybindings = cfg.keybindings();
+    let history: Box<dyn HistoryBackend> = match cfg.history.backend {
+        history::Kind::Cliphist => {
+            let cliphist = match cfg.cliphist.reader {
+                config::ClipHistReader::Cli => cliphist::new(&cfg.cliphist.path),
+                config::ClipHistReader::Db => {
+                    let db_path = match cfg.cliphist.db_path.take() {
+                        Some(path) => path,
+                        None => cliphist::default_db_path()?,
+                    };
+                    cliphist::new(&cfg.cliphist.path).with_db(db_path)
+                }
+            };
+            Box::new(cliphist)
+        }
+        history::Kind::Clipman => {
+            let path = match cfg.history.clipman_path.take() {
+                Some(path) => path,
+                None => history::clipman::default_path()?,
+            };
+            Box::new(history::clipman::new(path))
+        }
+        history::Kind::Store => {
+            let path = match cfg.history.store_path.take() {
+                Some(path) => path,
+                None => history::store::default_path()?,
+            };
+            Box::new(history::store::new(path))
+        }
+    };
+    let retention = retention::new(&cfg.retention)?;
+    let pinned = match cfg.history.pinned_path.take() {
+        Some(path) => path,
+        None => history::pinned::default_path()?,
+    };
+    let pinned = history::pinned::new(pinned);
+    let secrets = match cfg.secrets.enabled {
+        true => {
+            let path = match cfg.secrets.path.take() {
+                Some(path) => path,
+                None => secret::default_path()?,
+            };
+            Some(secret::new(&cfg.secrets.patterns, cfg.secrets.ttl, path)?)
+        }
+        false => None,
+    };
+    let index = match cfg.history.full_text_search {
+        true => Some(index::new(index::default_path()?)?),
+        false => None,
+    };
     let cache = cache::SimpleCache::new("rofi-cliphist/thumbs-new").expect("Error creating cache");
     let clipboard = clipboard::new(cfg.clipboard.path);
-    let rofi = rofi::new(cfg.rofi.path);
+
+    if let Some(command) = command {
+        let history = history.as_ref();
+        return match command {
+            Cmd::Prune { dry_run } => prune(&retention, history, dry_run),
+            Cmd::List { json, kind } => {
+                let entries = listed(history, &pinned, secrets.as_ref())?;
+                list(&entries, kind, json)
+            }
+            Cmd::Get { id, reveal } => {
+                let content = backend(&id, history, &pinned).decode(&id)?;
+                // e.g. the fzf preview, copying still delivers the real content
+                if !reveal
+                    && secrets
+                        .as_ref()
+                        .is_some_and(|s| s.detect(&String::from_utf8_lossy(&content)))
+                {
+                    bail!("Entry {id} looks like a secret, pass --reveal to print it");
+                }
+                print(&content)
+            }
+            Cmd::Copy { id } => {
+                let content = backend(&id, history, &pinned).decode(&id)?;
+                clipboard.copy(content)
+            }
+            Cmd::Delete { ids } => delete(history, ids),
+            Cmd::Stats => {
+                let entries = listed(history, &pinned, secrets.as_ref())?;
+                stats(&entries, history, &pinned, index.as_ref())
+            }
+            Cmd::Cache {
+                command: CacheCmd::Gc,
+            } => cache_gc(history, &pinned, &cache, index),
+        };
+    }
+
+    let mut snippets = std::mem::take(&mut cfg.snippets);
+    let snippets_dir = match cfg.snippets_dir.take() {
+        Some(dir) => Some(dir),
+        None => Some(snippet::default_dir()?).filter(|dir| dir.exists()),
+    };
+    if let Some(dir) = snippets_dir {
+        snippets.extend(snippet::load_dir(&dir)?);
+    }
+    let launcher: Box<dyn Launcher<Action>> = match cfg.launcher.kind {
+        launcher::Kind::Rofi => {
+            let path = cfg.launcher.path.unwrap_or(cfg.rofi.path);
+            if rofi::is_script_mode() {
+                Box::new(rofi::script(path, input)?)
+            } else {
+                Box::new(rofi::new(path))
+            }
+        }
+        launcher::Kind::Fzf => Box::new(launcher::fzf::new(
+            cfg.launcher
+                .path
+                .unwrap_or(launcher::Kind::Fzf.bin().into()),
+            match cfg.fzf.text_preview {
+                Some(preview) => preview,
+                None => text_preview(
+                    config_path.as_deref(),
+                    cfg.history.backend,
+                    &cfg.cliphist.path,
+                )?,
+            },
+            cfg.fzf.image_preview,
+        )),
+        kind => Box::new(launcher::dmenu::new(
+            kind,
+            cfg.launcher.path.unwrap_or(kind.bin().into()),
+        )?),
+    };
 
     debug!("Starting ClipHistMode");
 
     ClipHistMode::new(
-        rofi,
+        launcher,
         cache,
-        cliphist,
+        history,
+        pinned,
         clipboard,
         rofi::cliphist_mode::ClipHistModeConfig {
-            text_mode: cfg.text_mode_config,
-            image_mode: cfg.image_mode_config,
-            delete_mode: cfg.delete_mode_config,
-            delete_previous_mode: cfg.delete_previous_config,
-            delete_next_mode: cfg.delete_next_config,
+            keybindings,
+            separator: cfg.clipboard.separator,
+            snippets,
+            transforms: transform::all(cfg.transform.filters),
+            store_transformed: cfg.transform.store,
+            opener: open::new(cfg.open),
+            secrets,
+            // once per menu, not on every script mode callback
+            retention: Some(retention).filter(|policy| !policy.is_empty() && !rofi::is_callback()),
+            dedupe: cfg.history.dedupe,
+            index,
         },
     )?
     .run()
 }
 
+/// Parse the command line.
+///
+/// When rofi runs us as a script mode, the selected entry is passed as the last argument, which
+/// mustn't be taken for a subcommand (e.g. a copied `prune`).
+fn parse_args() -> Args {
+    let mut args = std::env::args_os().collect::<Vec<_>>();
+    let retv = std::env::var(rofi::script::ROFI_RETV).unwrap_or_default();
+    if !retv.is_empty() && retv != "0" && args.len() > 1 {
+        args.insert(args.len() - 1, "--".into());
+    }
+
+    Args::parse_from(args)
+}
+
+/// Print the entries breaking the retention rules, deleting them unless it's a dry run.
+fn prune(policy: &Policy, history: &dyn HistoryBackend, dry_run: bool) -> anyhow::Result<()> {
+    if policy.is_empty() {
+        println!("No retention rules configured");
+        return Ok(());
+    }
+    if policy.has_max_age()
+        && history
+            .meta()
+            .context("Error reading history metadata")?
+            .is_empty()
+    {
+        println!(
+            "Ignoring max_age_days, the history backend doesn't record when entries were copied"
+        );
+    }
+
+    let (entries, verb) = match dry_run {
+        true => (policy.prunable(history)?, "Would delete"),
+        false => (policy.prune(history)?, "Deleted"),
+    };
+    for entry in &entries {
+        println!("{verb} {}: {} ({})", entry.id, entry.label, entry.reason);
+    }
+    println!("{verb} {} entries", entries.len());
+
+    Ok(())
+}
+
+/// Default fzf text preview: the `get` subcommand of this executable, using the same config and
+/// backend, so pinned entries are previewed as well as the history's.
+fn text_preview(
+    config: Option<&Path>,
+    backend: history::Kind,
+    cliphist: &str,
+) -> anyhow::Result<String> {
+    let exe = std::env::current_exe().context("Error getting the executable path")?;
+    let backend = backend
+        .to_possible_value()
+        .context("Invalid history backend")?;
+
+    let mut command = vec![transform::shell_quote(&exe.to_string_lossy())];
+    if let Some(config) = config {
+        command.extend([
+            "-f".into(),
+            transform::shell_quote(&config.to_string_lossy()),
+        ]);
+    }
+    command.extend([
+        "-b".into(),
+        backend.get_name().into(),
+        "-c".into(),
+        transform::shell_quote(cliphist),
+        "get".into(),
+        "{id}".into(),
+    ]);
+
+    Ok(command.join(" "))
+}
+
+/// Where an entry is stored, the history or the pinned entries.
+fn backend<'a>(
+    id: &str,
+    history: &'a dyn HistoryBackend,
+    pinned: &'a Pinned,
+) -> &'a dyn HistoryBackend {
+    match pinned::is_pinned(id) {
+        true => pinned,
+        false => history,
+    }
+}
+
+/// Entries as the menu lists them: pinned ones first, secrets masked and the expired ones left
+/// out (the menu and roto-clipd delete them).
+fn listed(
+    history: &dyn HistoryBackend,
+    pinned: &Pinned,
+    secrets: Option<&Secrets>,
+) -> anyhow::Result<Vec<ClipHistEntry>> {
+    let mut entries = pinned.list().context("Error listing pinned entries")?;
+    let mut listed = history.list().context("Error listing clipboard history")?;
+    let copies = pinned
+        .copies(&entries, history, &listed)
+        .context("Error reading pinned entries")?;
+    listed.retain(|e| !copies.contains(&RofiEntry::id(e)));
+    entries.extend(listed);
+    if let Some(secrets) = secrets {
+        // read-only, the countdowns are only started by the menu and roto-clipd
+        let expired = secrets
+            .hide(&mut entries, |id| backend(id, history, pinned).decode(id))
+            .context("Error masking secrets")?;
+        entries.retain(|e| !expired.contains(&RofiEntry::id(e)));
+    }
+
+    Ok(entries)
+}
+
+/// Print the entries of the given type, all of them if none.
+fn list(entries: &[ClipHistEntry], kind: Option<EntryType>, json: bool) -> anyhow::Result<()> {
+    let entries = entries
+        .iter()
+        .filter(|e| kind.is_none_or(|kind| EntryType::of(e) == kind));
+
+    if !json {
+        let lines = entries
+            .map(|entry| format!("{}\t{}\n", RofiEntry::id(entry), entry.label()))
+            .collect::<String>();
+        return print(lines.as_bytes());
+    }
+
+    let values = entries
+        .map(|entry| {
+            let id = RofiEntry::id(entry);
+            let mut value = json!({
+                "id": id,
+                "type": EntryType::of(entry).name(),
+                "label": entry.label(),
+                "pinned": pinned::is_pinned(&id),
+            });
+            let (mime, size) = match entry {
+                ClipHistEntry::Text { category, .. } => {
+                    value["category"] = category.name().into();
+                    (None, 0)
+                }
+                ClipHistEntry::Image {
+                    content_type,
+                    size,
+                    dimensions,
+                    ..
+                } => {
+                    if let Some((width, height)) = dimensions {
+                        value["width"] = (*width).into();
+                        value["height"] = (*height).into();
+                    }
+                    (Some(mime::from_extension(content_type)), *size)
+                }
+                ClipHistEntry::Binary { mime, size, .. } => (Some(mime.as_str()), *size),
+            };
+            if let Some(mime) = mime {
+                value["mime"] = mime.into();
+            }
+            // 0 when unknown
+            if size > 0 {
+                value["size"] = size.into();
+            }
+            value
+        })
+        .collect::<Vec<_>>();
+    let mut json = serde_json::to_string_pretty(&values).context("Error serializing entries")?;
+    json.push('\n');
+
+    print(json.as_bytes())
+}
+
+/// Write to stdout, a reader closing the pipe early (e.g. `head`) not being an error.
+fn print(content: &[u8]) -> anyhow::Result<()> {
+    match std::io::stdout().lock().write_all(content) {
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+        result => result.context("Error writing to stdout"),
+    }
+}
+
+/// Delete entries from the history, failing if any couldn't be.
+fn delete(history: &dyn HistoryBackend, ids: Vec<String>) -> anyhow::Result<()> {
+    let (pinned, ids): (Vec<_>, Vec<_>) = ids.into_iter().partition(|id| pinned::is_pinned(id));
+    if !pinned.is_empty() {
+        bail!(
+            "Pinned entries have to be unpinned first: {}",
+            pinned.join(", ")
+        );
+    }
+
+    let deleted = history
+        .delete_many(&ids)
+        .context("Error deleting history entries")?;
+    let missing = ids
+        .iter()
+        .filter(|id| !deleted.contains(id))
+        .cloned()
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        bail!("Error deleting entries: {}", missing.join(", "));
+    }
+
+    Ok(())
+}
+
+/// Print the number and size of the entries per type, along with the oldest one when the backend
+/// records it.
+///
+/// Backends that don't record the entries' metadata have the texts their previews cut off decoded
+/// to be sized.
+fn stats(
+    entries: &[ClipHistEntry],
+    history: &dyn HistoryBackend,
+    pinned: &Pinned,
+    index: Option<&TextIndex>,
+) -> anyhow::Result<()> {
+    let meta = history.meta().context("Error reading history metadata")?;
+
+    let mut totals = [(0, 0); 3];
+    for entry in entries {
+        let kind = match entry {
+            ClipHistEntry::Text { .. } => 0,
+            ClipHistEntry::Image { .. } => 1,
+            ClipHistEntry::Binary { .. } => 2,
+        };
+        let id = RofiEntry::id(entry);
+        let size = retention::size(backend(&id, history, pinned), entry, meta.get(&id));
+        totals[kind].0 += 1;
+        totals[kind].1 += size;
+    }
+    for (name, (count, size)) in ["Texts", "Images", "Files"].iter().zip(totals) {
+        println!("{name}: {count} ({})", rofi::human_size(size));
+    }
+
+    let pinned = entries
+        .iter()
+        .filter(|e| pinned::is_pinned(&RofiEntry::id(*e)))
+        .count();
+    let secrets = entries
+        .iter()
+        .filter(
+            |e| matches!(e, ClipHistEntry::Text { category, .. } if *category == Category::Secret),
+        )
+        .count();
+    println!("Pinned: {pinned}");
+    println!("Secrets: {secrets}");
+    if let Some(index) = index {
+        println!("Indexed texts: {}", index.len());
+    }
+    if let Some(oldest) = meta.values().map(|meta| meta.first_seen).min() {
+        let date = chrono::DateTime::from_timestamp(oldest as i64, 0)
+            .context("Invalid timestamp")?
+            .with_timezone(&chrono::Local);
+        println!("Oldest: {}", date.format("%Y-%m-%d %H:%M"));
+    }
+
+    Ok(())
+}
+
+/// Remove the cached thumbnails and indexed texts of the entries no longer in the history.
+fn cache_gc(
+    history: &dyn HistoryBackend,
+    pinned: &Pinned,
+    cache: &SimpleCache,
+    index: Option<TextIndex>,
+) -> anyhow::Result<()> {
+    let mut entries = pinned.list().context("Error listing pinned entries")?;
+    entries.extend(history.list().context("Error listing clipboard history")?);
+
+    let removed = cache
+        .prune(cliphist_mode::cache_ids(&entries))
+        .context("Error pruning cache")?;
+    println!("Removed {removed} cached thumbnails");
+    if let Some(mut index) = index {
+        let removed = index.retain(&entries)?;
+        println!("Removed {removed} indexed texts");
+    }
+
+    Ok(())
+}
+
 fn merge_args_into_config(cfg: &mut config::Config, args: Args) {
+    cfg.launcher.kind = args.launcher.unwrap_or(cfg.launcher.kind);
+    cfg.history.backend = args.backend.unwrap_or(cfg.history.backend);
     cfg.rofi.path = args.rofi_path.unwrap_or(cfg.rofi.path.clone());
     cfg.clipboard.path = args.clipboard_path.unwrap_or(cfg.clipboard.path.clone());
     cfg.cliphist.path = args.cliphist_path.unwrap_or(cfg.cliphist.path.clone());
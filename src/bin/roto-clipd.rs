@@ -0,0 +1,222 @@
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use clap::{Parser, Subcommand};
+use log::{debug, info, warn, Level};
+use roto::{
+    config,
+    history::store,
+    secret::{self, Secrets},
+};
+
+/// How often expired secrets are deleted while watching the clipboard.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+/// How often `wl-paste` is checked for having exited in the meantime.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Keeps the clipboard history in the built-in store.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Show verbose output
+    #[command(flatten)]
+    verbose: clap_verbosity_flag::Verbosity,
+
+    /// Sets a custom config file
+    #[arg(short = 'f', long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Path to the store, defaults to `$XDG_DATA_HOME/roto/history`
+    #[arg(short, long, value_name = "DIR")]
+    store_path: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Cmd>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Cmd {
+    /// Store the content read from stdin, e.g. `wl-paste --watch roto-clipd store`
+    Store {
+        /// MIME type of the content, sniffed from it if not set
+        #[arg(short, long)]
+        mime: Option<String>,
+    },
+    /// Run `wl-paste --watch`, storing every new selection (the default)
+    Watch {
+        /// Path to wl-paste executable
+        #[arg(short, long, default_value = "wl-paste")]
+        wl_paste_path: String,
+    },
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    simple_logger::init_with_level(args.verbose.log_level().unwrap_or(Level::Error))?;
+
+    let cfg = if let Some(config_path) = &args.config {
+        info!("Using custom config file: {config_path:?}");
+        config::load(config_path)?
+    } else {
+        match config::load_default() {
+            Ok(c) => c,
+            Err(e) => match e.downcast_ref::<std::io::Error>() {
+                Some(io_err) if io_err.kind() == std::io::ErrorKind::NotFound => {
+                    debug!("Config file not found, using default config");
+                    config::Config::default()
+                }
+                _ => bail!("Error reading config: {:?}", e),
+            },
+        }
+    };
+
+    let store_path = match args.store_path.or(cfg.history.store_path.clone()) {
+        Some(path) => path,
+        None => store::default_path()?,
+    };
+
+    // only when they're deleted, masking is left to the menu
+    let secrets = match cfg.secrets.enabled && cfg.secrets.ttl > 0 {
+        true => {
+            let path = match cfg.secrets.path.clone() {
+                Some(path) => path,
+                None => secret::default_path()?,
+            };
+            Some(secret::new(&cfg.secrets.patterns, cfg.secrets.ttl, path)?)
+        }
+        false => None,
+    };
+
+    match args.command.unwrap_or(Cmd::Watch {
+        wl_paste_path: "wl-paste".into(),
+    }) {
+        Cmd::Store { mime } => {
+            store_stdin(&cfg.history, &store_path, mime.as_deref())?;
+            expire(secrets.as_ref(), &store_path)
+        }
+        Cmd::Watch { wl_paste_path } => watch(
+            &wl_paste_path,
+            args.config.as_deref(),
+            &store_path,
+            secrets.as_ref(),
+        ),
+    }
+}
+
+/// Store one selection read from stdin, honoring the limits of the configuration.
+fn store_stdin(cfg: &config::History, store_path: &Path, mime: Option<&str>) -> anyhow::Result<()> {
+    // set by `wl-paste --watch`, "sensitive" being used by password managers
+    match std::env::var("CLIPBOARD_STATE").as_deref() {
+        Ok("sensitive") => {
+            debug!("Ignoring sensitive selection");
+            return Ok(());
+        }
+        Ok("nil") | Ok("clear") => {
+            debug!("Clipboard cleared, nothing to store");
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let mut content = Vec::new();
+    std::io::stdin()
+        .take(cfg.max_entry_size + 1)
+        .read_to_end(&mut content)
+        .context("Error reading stdin")?;
+
+    if content.is_empty() || content.iter().all(u8::is_ascii_whitespace) {
+        debug!("Ignoring empty selection");
+        return Ok(());
+    }
+    if content.len() as u64 > cfg.max_entry_size {
+        warn!(
+            "Ignoring selection bigger than {} bytes",
+            cfg.max_entry_size
+        );
+        return Ok(());
+    }
+
+    let store = store::new(store_path);
+    let record = store.insert(&content, mime)?;
+    info!("Stored entry {} ({} bytes)", record.id, record.size);
+
+    store.trim(cfg.max_entries)?;
+
+    Ok(())
+}
+
+/// Delete the expired secrets of the store, if secrets expire.
+fn expire(secrets: Option<&Secrets>, store_path: &Path) -> anyhow::Result<()> {
+    let Some(secrets) = secrets else {
+        return Ok(());
+    };
+
+    let deleted = secrets.expire(&store::new(store_path))?;
+    if !deleted.is_empty() {
+        info!("Deleted {} expired secrets", deleted.len());
+    }
+
+    Ok(())
+}
+
+/// Let `wl-paste` call us back with every new selection, deleting the expired secrets in the
+/// meantime.
+fn watch(
+    wl_paste_path: &str,
+    config: Option<&Path>,
+    store_path: &Path,
+    secrets: Option<&Secrets>,
+) -> anyhow::Result<()> {
+    let exe = std::env::current_exe().context("Error getting roto-clipd's path")?;
+
+    let mut args = vec![
+        "--watch".to_string(),
+        exe.to_string_lossy().to_string(),
+        "--store-path".into(),
+        store_path.to_string_lossy().to_string(),
+    ];
+    if let Some(config) = config {
+        args.extend(["--config".into(), config.to_string_lossy().to_string()]);
+    }
+    args.push("store".into());
+
+    debug!("Executing {wl_paste_path:?} with args: {args:?}");
+    let mut child = Command::new(wl_paste_path)
+        .args(&args)
+        .stdin(Stdio::null())
+        .spawn()
+        .context("Error executing wl-paste")?;
+
+    let status = match secrets {
+        None => child.wait().context("Error waiting for wl-paste")?,
+        Some(_) => {
+            let mut next_sweep = Instant::now();
+            loop {
+                if let Some(status) = child.try_wait().context("Error waiting for wl-paste")? {
+                    break status;
+                }
+                if Instant::now() >= next_sweep {
+                    // best effort, a failed sweep is retried on the next one
+                    if let Err(e) = expire(secrets, store_path) {
+                        warn!("Error deleting expired secrets: {e:?}");
+                    }
+                    next_sweep = Instant::now() + SWEEP_INTERVAL;
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+    };
+
+    if !status.success() {
+        bail!("wl-paste exited with {status}");
+    }
+
+    Ok(())
+}
@@ -27,6 +27,12 @@ impl SimpleCache {
         })
     }
 
+    /// Cache stored in `cache_dir`, e.g. a temporary directory.
+    #[cfg(test)]
+    pub(crate) fn at(cache_dir: PathBuf) -> Self {
+        Self { cache_dir }
+    }
+
     /// Prune the cache directory, removing all files that are not in the `excludes` list.
     pub fn prune(&self, excludes: Vec<String>) -> anyhow::Result<usize> {
         trace!("Pruning cache directory, excluding: {excludes:?}");
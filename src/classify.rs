@@ -0,0 +1,203 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::color;
+
+static UUID: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}$")
+        .unwrap()
+});
+static URL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^([a-zA-Z][a-zA-Z0-9+.-]*://|www\.)\S+$").unwrap());
+static EMAIL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(mailto:)?[a-zA-Z0-9._%+-]+@[a-zA-Z0-9-]+(\.[a-zA-Z0-9-]+)*\.[a-zA-Z]{2,}$")
+        .unwrap()
+});
+static NUMBER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^([+-]?([0-9]+(,[0-9]{3})*(\.[0-9]+)?|\.[0-9]+)([eE][+-]?[0-9]+)?|0x[0-9a-fA-F]+)$",
+    )
+    .unwrap()
+});
+static PHONE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\+?[0-9(][0-9 ().-]{4,}[0-9]$").unwrap());
+static DATE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[0-9]{4}-[0-9]{2}-[0-9]{2}$").unwrap());
+static PATH: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^((~|\.\.?)?(/[^/\s][^/]*)+/?|~/?|[a-zA-Z]:\\[^\n]*)$").unwrap());
+
+/// Kind of content of a text entry.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    Url,
+    Email,
+    Path,
+    Color,
+    Json,
+    Number,
+    Uuid,
+    Phone,
+    Code,
+    Text,
+    /// A password or token, detected by [`crate::secret`] rather than classified
+    Secret,
+}
+
+impl Category {
+    /// Name used to filter the entries, typing `name:`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Url => "url",
+            Category::Email => "email",
+            Category::Path => "path",
+            Category::Color => "color",
+            Category::Json => "json",
+            Category::Number => "number",
+            Category::Uuid => "uuid",
+            Category::Phone => "phone",
+            Category::Code => "code",
+            Category::Text => "text",
+            Category::Secret => "secret",
+        }
+    }
+
+    /// Icon of the category in the freedesktop naming scheme.
+    pub fn icon(&self) -> &'static str {
+        match self {
+            Category::Url => "text-html",
+            Category::Email => "internet-mail",
+            Category::Path => "folder",
+            Category::Color => "applications-graphics",
+            Category::Json => "application-json",
+            Category::Number => "accessories-calculator",
+            Category::Uuid => "emblem-system",
+            Category::Phone => "call-start",
+            Category::Code => "text-x-script",
+            Category::Text => "text-x-generic",
+            Category::Secret => "dialog-password",
+        }
+    }
+}
+
+/// Guess the kind of a text.
+///
+/// Multi-line code can only be told apart when the full text is given, previews have their
+/// whitespace collapsed.
+pub fn classify(text: &str) -> Category {
+    let text = text.trim();
+
+    if text.contains('\n') {
+        return if is_json(text) {
+            Category::Json
+        } else if is_code(text) {
+            Category::Code
+        } else {
+            Category::Text
+        };
+    }
+
+    if UUID.is_match(text) {
+        Category::Uuid
+    } else if URL.is_match(text) {
+        Category::Url
+    } else if EMAIL.is_match(text) {
+        Category::Email
+    } else if color::parse(text).is_some() {
+        Category::Color
+    } else if NUMBER.is_match(text) {
+        Category::Number
+    } else if is_phone(text) {
+        Category::Phone
+    } else if PATH.is_match(text) {
+        Category::Path
+    } else if is_json(text) {
+        Category::Json
+    } else {
+        Category::Text
+    }
+}
+
+fn is_json(text: &str) -> bool {
+    let delimited = (text.starts_with('{') && text.ends_with('}'))
+        || (text.starts_with('[') && text.ends_with(']'));
+
+    delimited && serde_json::from_str::<serde::de::IgnoredAny>(text).is_ok()
+}
+
+/// Phone numbers have 7 to 15 digits, dates look alike so they're excluded.
+fn is_phone(text: &str) -> bool {
+    let digits = text.chars().filter(char::is_ascii_digit).count();
+
+    (7..=15).contains(&digits) && PHONE.is_match(text) && !DATE.is_match(text)
+}
+
+/// Most lines end like statements or blocks, or are indented.
+fn is_code(text: &str) -> bool {
+    let lines = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect::<Vec<_>>();
+    let code = lines
+        .iter()
+        .filter(|line| {
+            line.starts_with([' ', '\t'])
+                || line
+                    .trim_end()
+                    .ends_with([';', '{', '}', '(', ')', '[', ']', ':', ',', '\\'])
+        })
+        .count();
+
+    code * 2 >= lines.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tells_dates_from_phones() {
+        assert_eq!(classify("2024-01-15"), Category::Text);
+        assert_eq!(classify("+33 6 12 34 56 78"), Category::Phone);
+        assert_eq!(classify("(555) 123-4567"), Category::Phone);
+        assert_eq!(classify("555-123-4567"), Category::Phone);
+    }
+
+    #[test]
+    fn tells_numbers_from_phones() {
+        assert_eq!(classify("1234567"), Category::Number);
+        assert_eq!(classify("1,234,567"), Category::Number);
+        assert_eq!(classify("-3.5e10"), Category::Number);
+        assert_eq!(classify("0x1F"), Category::Number);
+        // too few digits for a phone number
+        assert_eq!(classify("12-34"), Category::Text);
+    }
+
+    #[test]
+    fn classifies_single_lines() {
+        assert_eq!(
+            classify("123e4567-e89b-12d3-a456-426614174000"),
+            Category::Uuid
+        );
+        assert_eq!(classify("https://example.com/a?b=c"), Category::Url);
+        assert_eq!(classify("www.example.com"), Category::Url);
+        assert_eq!(classify("mailto:me@example.com"), Category::Email);
+        assert_eq!(classify("#ff8800"), Category::Color);
+        assert_eq!(classify("~/.config/roto"), Category::Path);
+        assert_eq!(classify(r"C:\Users\me"), Category::Path);
+        assert_eq!(classify(r#"{"a": [1, 2]}"#), Category::Json);
+        assert_eq!(classify("hello world"), Category::Text);
+    }
+
+    #[test]
+    fn classifies_multiple_lines() {
+        assert_eq!(classify("{\n  \"a\": 1\n}"), Category::Json);
+        assert_eq!(
+            classify("fn main() {\n    println!(\"hi\");\n}"),
+            Category::Code
+        );
+        assert_eq!(classify("Dear all,\nsee you tomorrow\nBye"), Category::Text);
+    }
+}
@@ -15,8 +15,10 @@ impl Clipboard {
     pub fn copy(&self, content: Vec<u8>) -> anyhow::Result<()> {
         trace!("Copying to clipboard");
 
+        // wl-copy forks to serve the selection, don't let it hold rofi's script pipe open
         let mut child = std::process::Command::new(&self.bin)
             .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
             .spawn()
             .context("Error executing clipboard")?;
 
@@ -1,18 +1,47 @@
 use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
     io::Write,
+    path::{Path, PathBuf},
     process::{Command, Stdio},
 };
 
 use anyhow::{bail, Context};
-use log::{debug, trace};
+use directories_next::BaseDirs;
+use log::{debug, trace, warn};
 use regex::Regex;
 
-use crate::cache::CacheEntry;
+use crate::{
+    cache::CacheEntry,
+    classify::{self, Category},
+    mime,
+};
+
+pub mod bbolt;
+
+/// Bucket where cliphist stores the entries, keyed by their big-endian id.
+const BUCKET: &[u8] = b"b";
+/// Length of the text previews, the same `cliphist list` uses by default.
+const PREVIEW_WIDTH: usize = 100;
 
 /// A given entry in the clipboard history.
+#[derive(Clone)]
 pub enum ClipHistEntry {
-    Text { id: String, title: String },
-    Image { id: String, content_type: String },
+    /// A text, `title` being a single line preview.
+    Text {
+        id: String,
+        title: String,
+        category: Category,
+    },
+    /// An image, `size` being 0 and `dimensions` `None` when unknown.
+    Image {
+        id: String,
+        content_type: String,
+        size: u64,
+        dimensions: Option<(u32, u32)>,
+    },
+    /// Non-image binary data, `size` being 0 when unknown.
+    Binary { id: String, mime: String, size: u64 },
 }
 
 /// Api for interacting with the `cliphist` clipboard manager.
@@ -20,6 +49,11 @@ pub struct ClipHist {
     bin: String,
     line_parser: Regex,
     binary_parser: Regex,
+    db: Option<PathBuf>,
+    snapshot: RefCell<Option<bbolt::Db>>,
+    /// Entries whose preview didn't tell texts from binaries, by id, once their content was
+    /// checked. cliphist never reuses ids, so they're only decoded once.
+    sniffed: RefCell<HashMap<String, ClipHistEntry>>,
 }
 
 /// Create a new instance of the `ClipHist` api.
@@ -28,14 +62,38 @@ pub fn new(bin: impl Into<String>) -> ClipHist {
     ClipHist {
         bin: bin.into(),
         line_parser: Regex::new(r"^(?P<idx>[0-9]+)\t(?P<value>.*)$").unwrap(),
-        binary_parser: Regex::new(r"^(\[\[\s)?binary.*(?P<ext>jpg|jpeg|png|bmp)").unwrap(),
+        binary_parser: Regex::new(
+            r"^\[\[ binary data (?P<size>[0-9.]+ [KMG]?i?B)(?: (?P<kind>[a-zA-Z0-9.+/-]+))?(?: (?P<width>[0-9]+)x(?P<height>[0-9]+))?",
+        )
+        .unwrap(),
+        db: None,
+        snapshot: RefCell::new(None),
+        sniffed: RefCell::new(HashMap::new()),
     }
 }
 
+/// Default location of cliphist's database.
+pub fn default_db_path() -> anyhow::Result<PathBuf> {
+    let dirs = BaseDirs::new().context("Error getting base directories")?;
+
+    Ok(dirs.cache_dir().join("cliphist").join("db"))
+}
+
 impl ClipHist {
+    /// Read the entries straight from cliphist's database file instead of spawning `cliphist`.
+    /// Changes (store, delete) still go through the CLI.
+    pub fn with_db(mut self, path: impl Into<PathBuf>) -> Self {
+        self.db = Some(path.into());
+        self
+    }
+
     /// List all entries in the clipboard history.
     pub fn list(&self) -> anyhow::Result<Vec<ClipHistEntry>> {
         trace!("Listing clipboard history");
+        if let Some(path) = &self.db {
+            return self.list_db(path);
+        }
+
         let history = Command::new(&self.bin)
             .arg("list")
             .output()
@@ -88,6 +146,93 @@ impl ClipHist {
         }
 
         debug!("Successfully removed entry with id: {id}");
+        self.snapshot.take();
+
+        Ok(())
+    }
+
+    /// Remove several entries streaming their ids to a single `cliphist delete` process.
+    ///
+    /// The history is listed again afterwards, so the ids that were actually removed are returned
+    /// even if cliphist failed midway.
+    pub fn remove_many(&self, ids: &[String]) -> anyhow::Result<Vec<String>> {
+        debug!("About to remove {} entries", ids.len());
+        let mut child = Command::new(&self.bin)
+            .arg("delete")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Error executing cliphist")?;
+
+        let lines = ids.iter().map(|id| format!("{id}\t\n")).collect::<String>();
+        let written = child
+            .stdin
+            .take()
+            .context("Failed to open stdin")?
+            .write_all(lines.as_bytes());
+        if let Err(e) = written {
+            warn!("Error writing ids to cliphist: {e}");
+        }
+
+        let output = child
+            .wait_with_output()
+            .context("Error executing cliphist")?;
+        if !output.status.success() {
+            warn!(
+                "Error executing cliphist: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        self.snapshot.take();
+
+        let remaining = self
+            .list()
+            .context("Error listing cliphist after deleting")?
+            .iter()
+            .map(|entry| entry_id(entry).to_string())
+            .collect::<HashSet<_>>();
+        let removed = ids
+            .iter()
+            .filter(|id| !remaining.contains(*id))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        debug!(
+            "Successfully removed {} of {} entries",
+            removed.len(),
+            ids.len()
+        );
+
+        Ok(removed)
+    }
+
+    /// Store a new entry in the clipboard history.
+    pub fn store(&self, content: Vec<u8>) -> anyhow::Result<()> {
+        trace!("Storing new entry ({} bytes)", content.len());
+        let mut child = Command::new(&self.bin)
+            .arg("store")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Error executing cliphist")?;
+
+        child
+            .stdin
+            .take()
+            .context("Failed to open stdin")?
+            .write_all(&content)
+            .context("Failed to write to stdin")?;
+
+        let status = child.wait().context("Error executing cliphist")?;
+
+        if !status.success() {
+            bail!("Error executing cliphist");
+        }
+
+        debug!("Successfully stored new entry");
+        self.snapshot.take();
 
         Ok(())
     }
@@ -95,6 +240,9 @@ impl ClipHist {
     /// Get the value of a given entry in the clipboard history.
     pub fn value_of(&self, id: String) -> anyhow::Result<Vec<u8>> {
         trace!("Getting value of entry with id: {id}");
+        if self.db.is_some() {
+            return self.value_of_db(&id);
+        }
 
         let value = Command::new(&self.bin)
             .arg("decode")
@@ -118,6 +266,54 @@ impl ClipHist {
         Ok(value.stdout)
     }
 
+    /// List the entries reading the database, newest first like `cliphist list`.
+    fn list_db(&self, path: &Path) -> anyhow::Result<Vec<ClipHistEntry>> {
+        let db = bbolt::open(path)?;
+
+        let history = match db.bucket(BUCKET)? {
+            Some(bucket) => bucket
+                .entries()?
+                .into_iter()
+                .rev()
+                .map(|(key, value)| db_entry(key, value))
+                .collect::<anyhow::Result<Vec<_>>>()?,
+            None => vec![],
+        };
+
+        debug!("Found {} entries in clipboard history", history.len());
+        self.snapshot.replace(Some(db));
+
+        Ok(history)
+    }
+
+    /// Get the value of an entry from the last database snapshot, reading it if needed.
+    fn value_of_db(&self, id: &str) -> anyhow::Result<Vec<u8>> {
+        let mut snapshot = self.snapshot.borrow_mut();
+        if snapshot.is_none() {
+            let path = self
+                .db
+                .as_ref()
+                .context("No cliphist database configured")?;
+            *snapshot = Some(bbolt::open(path)?);
+        }
+        let db = snapshot.as_ref().context("No cliphist database snapshot")?;
+
+        let key = id
+            .parse::<u64>()
+            .context(format!("Invalid cliphist id: {id}"))?
+            .to_be_bytes();
+        let value = db
+            .bucket(BUCKET)?
+            .map(|bucket| bucket.get(&key))
+            .transpose()?
+            .flatten()
+            .context(format!("Entry {id} not found in cliphist database"))?;
+
+        debug!("Got value of entry with id: {} ({}) bytes", id, value.len());
+
+        Ok(value.to_vec())
+    }
+
     fn parse_entry(&self, line: &str) -> anyhow::Result<ClipHistEntry> {
         let parsed = self
             .line_parser
@@ -128,14 +324,60 @@ impl ClipHist {
         let value = &parsed["value"];
 
         let entry: ClipHistEntry = if let Some(binary) = self.binary_parser.captures(value) {
+            // depending on its version, cliphist reports either an extension or a MIME type
+            let kind = binary.name("kind").map(|kind| kind.as_str());
+            let mime = match kind {
+                Some(kind) if kind.contains('/') => kind.to_string(),
+                Some(kind) => mime::from_extension(kind).into(),
+                None => mime::OCTET_STREAM.into(),
+            };
+            let dimensions = binary
+                .name("width")
+                .zip(binary.name("height"))
+                .and_then(|(w, h)| Some((w.as_str().parse().ok()?, h.as_str().parse().ok()?)));
+            let mut entry = binary_entry(id, &mime, parse_size(&binary["size"]), dimensions);
+            // keep the reported extension (e.g. `jpg`), the cached thumbnails are named after it
+            if let (ClipHistEntry::Image { content_type, .. }, Some(kind)) = (&mut entry, kind) {
+                if !kind.contains('/') {
+                    *content_type = kind.into();
+                }
+            }
+            entry
+        } else if value.contains(char::REPLACEMENT_CHARACTER) {
+            // cliphist only recognizes images, other binaries are previewed as (invalid) text, but
+            // so are texts containing `�`, only the content tells them apart
+            if let Some(entry) = self.sniffed.borrow().get(&id) {
+                return Ok(entry.clone());
+            }
+            match self.value_of(id.clone()) {
+                Ok(content) => {
+                    let entry = entry(id.clone(), &content);
+                    self.sniffed.borrow_mut().insert(id, entry.clone());
+                    entry
+                }
+                Err(e) => {
+                    warn!("Error decoding entry {id}, listing it as text: {e:?}");
+                    ClipHistEntry::Text {
+                        id,
+                        title: value.into(),
+                        category: classify::classify(value),
+                    }
+                }
+            }
+        } else if mime::sniff(value.as_bytes()).is_some_and(mime::is_image) {
+            // SVG images are text, so they're listed by their (truncated) markup
             ClipHistEntry::Image {
                 id,
-                content_type: binary["ext"].into(),
+                content_type: "svg".into(),
+                size: 0,
+                dimensions: None,
             }
         } else {
+            // only the preview is known, multi-line code can't be told apart
             ClipHistEntry::Text {
                 id,
                 title: value.into(),
+                category: classify::classify(value),
             }
         };
 
@@ -143,11 +385,195 @@ impl ClipHist {
     }
 }
 
+fn entry_id(entry: &ClipHistEntry) -> &str {
+    match entry {
+        ClipHistEntry::Text { id, .. }
+        | ClipHistEntry::Image { id, .. }
+        | ClipHistEntry::Binary { id, .. } => id,
+    }
+}
+
+/// Build an entry from a database key/value pair.
+fn db_entry(key: &[u8], value: &[u8]) -> anyhow::Result<ClipHistEntry> {
+    let id = u64::from_be_bytes(key.try_into().context("Invalid cliphist key")?).to_string();
+
+    Ok(entry(id, value))
+}
+
+/// Build the entry of some content, previewing text the way `cliphist list` does.
+pub fn entry(id: String, value: &[u8]) -> ClipHistEntry {
+    if let Some(mime) = mime::sniff(value) {
+        return binary_entry(id, mime, value.len() as u64, mime::dimensions(value));
+    }
+
+    let text = String::from_utf8_lossy(value);
+    ClipHistEntry::Text {
+        id,
+        title: preview(&text),
+        category: classify::classify(&text),
+    }
+}
+
+/// Single line preview of a text, whitespace collapsed and truncated to [`PREVIEW_WIDTH`].
+pub fn preview(text: &str) -> String {
+    let mut title = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if title.chars().count() > PREVIEW_WIDTH {
+        title = title.chars().take(PREVIEW_WIDTH - 1).collect::<String>() + "…";
+    }
+
+    title
+}
+
+/// Build the entry of some binary content, images getting their own variant.
+fn binary_entry(
+    id: String,
+    mime: &str,
+    size: u64,
+    dimensions: Option<(u32, u32)>,
+) -> ClipHistEntry {
+    if mime::is_image(mime) {
+        ClipHistEntry::Image {
+            id,
+            content_type: mime::extension(mime).into(),
+            size,
+            dimensions,
+        }
+    } else {
+        ClipHistEntry::Binary {
+            id,
+            mime: mime.into(),
+            size,
+        }
+    }
+}
+
+/// Parse a human readable size, as printed by cliphist (e.g. `12 KiB`), into bytes.
+fn parse_size(size: &str) -> u64 {
+    let (value, unit) = size.split_once(' ').unwrap_or((size, "B"));
+    let multiplier = match unit {
+        "KiB" => 1 << 10,
+        "MiB" => 1 << 20,
+        "GiB" => 1 << 30,
+        _ => 1,
+    };
+
+    (value.parse::<f64>().unwrap_or_default() * multiplier as f64) as u64
+}
+
 impl CacheEntry for ClipHistEntry {
     fn id(&self) -> String {
         match self {
             ClipHistEntry::Text { id, .. } => id.to_string(),
-            ClipHistEntry::Image { id, content_type } => format!("{id}.{content_type}"),
+            ClipHistEntry::Image {
+                id, content_type, ..
+            } => format!("{id}.{content_type}"),
+            ClipHistEntry::Binary { id, .. } => id.to_string(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, os::unix::fs::PermissionsExt, path::PathBuf};
+
+    use super::*;
+
+    /// cliphist reading a fixture database, generated by `tests/fixtures/bbolt.py`.
+    fn fixture(name: &str) -> ClipHist {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name);
+        new("cliphist").with_db(path)
+    }
+
+    #[test]
+    fn lists_database_newest_first() {
+        let entries = fixture("cliphist-tree.db").list().unwrap();
+
+        let ids = entries.iter().map(entry_id).collect::<Vec<_>>();
+        assert_eq!(ids, vec!["5", "4", "3", "2", "1"]);
+    }
+
+    #[test]
+    fn decodes_database_entries() {
+        let entries = fixture("cliphist-tree.db").list().unwrap();
+
+        assert!(matches!(
+            &entries[0],
+            ClipHistEntry::Text { title, .. } if title.chars().count() == PREVIEW_WIDTH && title.ends_with('…')
+        ));
+        assert!(matches!(
+            &entries[2],
+            ClipHistEntry::Image { content_type, dimensions: Some((2, 2)), .. } if content_type == "png"
+        ));
+        assert!(matches!(
+            &entries[3],
+            ClipHistEntry::Text { title, category: Category::Url, .. } if title == "https://example.com"
+        ));
+    }
+
+    #[test]
+    fn previews_inline_bucket() {
+        let entries = fixture("cliphist-inline.db").list().unwrap();
+
+        assert!(matches!(
+            &entries[0],
+            ClipHistEntry::Text { id, title, .. } if id == "2" && title == "foo bar"
+        ));
+    }
+
+    #[test]
+    fn keeps_reported_image_extensions() {
+        let cliphist = new("cliphist");
+
+        let entry = cliphist
+            .parse_entry("1\t[[ binary data 12 KiB jpg 10x20 ]]")
+            .unwrap();
+        assert!(matches!(
+            &entry,
+            ClipHistEntry::Image { content_type, size: 12288, dimensions: Some((10, 20)), .. } if content_type == "jpg"
+        ));
+        assert_eq!(CacheEntry::id(&entry), "1.jpg");
+
+        let entry = cliphist
+            .parse_entry("2\t[[ binary data 1 KiB image/jpeg 10x20 ]]")
+            .unwrap();
+        assert_eq!(CacheEntry::id(&entry), "2.jpeg");
+    }
+
+    #[test]
+    fn decodes_ambiguous_previews_once() {
+        // a cliphist listing a PDF, previewed as invalid text, and counting the decodes
+        let dir = tempfile::tempdir().unwrap();
+        let bin = dir.path().join("cliphist");
+        let decodes = dir.path().join("decodes");
+        fs::write(
+            &bin,
+            format!(
+                "#!/bin/sh\ncase \"$1\" in\nlist) printf '1\\t%%PDF-1.7 \\357\\277\\275\\n';;\ndecode) echo >> {decodes:?}; printf '%%PDF-1.7 \\377';;\nesac\n"
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&bin, fs::Permissions::from_mode(0o755)).unwrap();
+        let cliphist = new(bin.to_string_lossy());
+
+        for _ in 0..3 {
+            let entries = cliphist.list().unwrap();
+            assert!(matches!(
+                &entries[0],
+                ClipHistEntry::Binary { mime, .. } if mime == "application/pdf"
+            ));
+        }
+        assert_eq!(fs::read_to_string(&decodes).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn gets_database_values() {
+        let cliphist = fixture("cliphist-tree.db");
+
+        assert_eq!(cliphist.value_of("4".into()).unwrap(), b"world");
+        assert_eq!(cliphist.value_of("5".into()).unwrap().len(), 3000);
+        assert!(cliphist.value_of("6".into()).is_err());
+        assert!(cliphist.value_of("nope".into()).is_err());
+    }
+}
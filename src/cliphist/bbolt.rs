@@ -0,0 +1,452 @@
+use std::{cmp::Ordering, fs::File, io::Read, path::Path};
+
+use anyhow::{bail, Context};
+use log::{debug, trace};
+
+/// Magic number identifying a bbolt meta page.
+const MAGIC: u32 = 0xED0C_DAED;
+/// Only supported data file version.
+const VERSION: u32 = 2;
+/// Size of the page header: id (u64), flags (u16), count (u16) and overflow (u32).
+const PAGE_HEADER_SIZE: usize = 16;
+/// Size of both branch and leaf page elements.
+const ELEMENT_SIZE: usize = 16;
+/// Size of a bucket header: root page id (u64) and sequence (u64).
+const BUCKET_HEADER_SIZE: usize = 16;
+/// Offset of the checksum inside the meta, which covers the preceding bytes.
+const META_CHECKSUM_OFFSET: usize = 56;
+/// Page size assumed when the first meta page is unreadable.
+const DEFAULT_PAGE_SIZE: usize = 4096;
+/// Trees deeper than this are considered corrupted (e.g. a page referencing itself).
+const MAX_DEPTH: usize = 64;
+
+const BRANCH_PAGE_FLAG: u16 = 0x01;
+const LEAF_PAGE_FLAG: u16 = 0x02;
+const BUCKET_LEAF_FLAG: u32 = 0x01;
+
+/// Read-only snapshot of a [bbolt](https://github.com/etcd-io/bbolt) database file.
+///
+/// The whole file is loaded in memory while holding a shared lock, the same bbolt readers take,
+/// so a concurrent writer can't leave us with a half written transaction.
+pub struct Db {
+    data: Vec<u8>,
+    page_size: usize,
+    root: u64,
+}
+
+/// A bucket of the database, its root being either a page or inlined in the parent's value.
+pub struct Bucket<'a> {
+    db: &'a Db,
+    root: Root<'a>,
+}
+
+enum Root<'a> {
+    Page(u64),
+    Inline(&'a [u8]),
+}
+
+/// A key/value pair stored in a leaf page.
+struct Element<'a> {
+    flags: u32,
+    key: &'a [u8],
+    value: &'a [u8],
+}
+
+/// Open a database file, reading its latest committed transaction.
+pub fn open(path: &Path) -> anyhow::Result<Db> {
+    trace!("Opening bbolt database: {path:?}");
+
+    let mut file = File::open(path).context(format!("Error opening database {path:?}"))?;
+    file.lock_shared()
+        .context(format!("Error locking database {path:?}"))?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)
+        .context(format!("Error reading database {path:?}"))?;
+    file.unlock()
+        .context(format!("Error unlocking database {path:?}"))?;
+
+    let first = Meta::parse(&data, 0);
+    let page_size = first
+        .as_ref()
+        .map(|m| m.page_size)
+        .unwrap_or(DEFAULT_PAGE_SIZE);
+    let second = Meta::parse(&data, page_size);
+
+    // both meta pages are written alternately, the latest valid one wins
+    let meta = match (first, second) {
+        (Some(a), Some(b)) => {
+            if a.txid >= b.txid {
+                a
+            } else {
+                b
+            }
+        }
+        (Some(m), None) | (None, Some(m)) => m,
+        (None, None) => bail!("Invalid bbolt database {path:?}: no valid meta page"),
+    };
+
+    debug!(
+        "Opened bbolt database {path:?} at txid {} ({} bytes pages)",
+        meta.txid, meta.page_size
+    );
+
+    Ok(Db {
+        data,
+        page_size: meta.page_size,
+        root: meta.root,
+    })
+}
+
+/// The fields of a meta page we care about.
+struct Meta {
+    page_size: usize,
+    root: u64,
+    txid: u64,
+}
+
+impl Meta {
+    /// Parse the meta stored in the page at `offset`, `None` if it's missing or corrupted.
+    fn parse(data: &[u8], offset: usize) -> Option<Self> {
+        let meta = data.get(offset + PAGE_HEADER_SIZE..)?;
+        let checksum = read_u64(meta, META_CHECKSUM_OFFSET)?;
+
+        if read_u32(meta, 0)? != MAGIC
+            || read_u32(meta, 4)? != VERSION
+            || fnv1a(meta.get(..META_CHECKSUM_OFFSET)?) != checksum
+        {
+            trace!("Invalid meta page at offset {offset}");
+            return None;
+        }
+
+        Some(Self {
+            page_size: read_u32(meta, 8)? as usize,
+            root: read_u64(meta, 16)?,
+            txid: read_u64(meta, 48)?,
+        })
+    }
+}
+
+impl Db {
+    /// Get a top level bucket by name.
+    pub fn bucket(&self, name: &[u8]) -> anyhow::Result<Option<Bucket<'_>>> {
+        let root = Bucket {
+            db: self,
+            root: Root::Page(self.root),
+        };
+
+        root.find(name)?
+            .filter(|e| e.flags & BUCKET_LEAF_FLAG != 0)
+            .map(|e| Bucket::from_value(self, e.value))
+            .transpose()
+    }
+
+    /// Get a page, including its overflow pages.
+    fn page(&self, id: u64) -> anyhow::Result<&[u8]> {
+        let start = usize::try_from(id)
+            .ok()
+            .and_then(|id| id.checked_mul(self.page_size))
+            .context(format!("Invalid page id {id}"))?;
+        let header = start
+            .checked_add(PAGE_HEADER_SIZE)
+            .and_then(|end| self.data.get(start..end))
+            .context(format!("Page {id} out of bounds"))?;
+        let overflow = read_u32(header, 12).context("reading page overflow")? as usize;
+        let end = (overflow + 1)
+            .checked_mul(self.page_size)
+            .and_then(|size| start.checked_add(size))
+            .context(format!("Page {id} out of bounds"))?;
+
+        self.data
+            .get(start..end.min(self.data.len()))
+            .context(format!("Page {id} out of bounds"))
+    }
+}
+
+impl<'a> Bucket<'a> {
+    /// Parse a bucket header stored as a value of the parent bucket.
+    fn from_value(db: &'a Db, value: &'a [u8]) -> anyhow::Result<Self> {
+        let root = read_u64(value, 0).context("Invalid bucket header")?;
+        let root = if root == 0 {
+            Root::Inline(
+                value
+                    .get(BUCKET_HEADER_SIZE..)
+                    .context("Invalid inline bucket")?,
+            )
+        } else {
+            Root::Page(root)
+        };
+
+        Ok(Self { db, root })
+    }
+
+    /// All the key/value pairs of the bucket (nested buckets excluded), sorted by key.
+    pub fn entries(&self) -> anyhow::Result<Vec<(&'a [u8], &'a [u8])>> {
+        Ok(self
+            .elements()?
+            .into_iter()
+            .filter(|e| e.flags & BUCKET_LEAF_FLAG == 0)
+            .map(|e| (e.key, e.value))
+            .collect())
+    }
+
+    /// Get the value of a given key.
+    pub fn get(&self, key: &[u8]) -> anyhow::Result<Option<&'a [u8]>> {
+        Ok(self
+            .find(key)?
+            .filter(|e| e.flags & BUCKET_LEAF_FLAG == 0)
+            .map(|e| e.value))
+    }
+
+    /// Find the leaf element of a given key, binary searching the pages from the root down.
+    fn find(&self, key: &[u8]) -> anyhow::Result<Option<Element<'a>>> {
+        let mut page = match self.root {
+            Root::Page(id) => self.db.page(id)?,
+            Root::Inline(page) => page,
+        };
+
+        for _ in 0..=MAX_DEPTH {
+            let flags = read_u16(page, 8).context("reading page flags")?;
+            let count = read_u16(page, 10).context("reading page count")? as usize;
+
+            if flags & BRANCH_PAGE_FLAG != 0 {
+                // the child holding the keys from its own up to the next one's
+                let idx = match search(count, |idx| Ok(branch_element(page, idx)?.0.cmp(key)))? {
+                    Ok(idx) => idx,
+                    Err(0) => return Ok(None),
+                    Err(idx) => idx - 1,
+                };
+                page = self.db.page(branch_element(page, idx)?.1)?;
+            } else if flags & LEAF_PAGE_FLAG != 0 {
+                return match search(count, |idx| Ok(leaf_element(page, idx)?.key.cmp(key)))? {
+                    Ok(idx) => leaf_element(page, idx).map(Some),
+                    Err(_) => Ok(None),
+                };
+            } else {
+                bail!("Unexpected bbolt page flags: {flags:#x}");
+            }
+        }
+
+        bail!("Corrupted bbolt database: tree deeper than {MAX_DEPTH} levels");
+    }
+
+    fn elements(&self) -> anyhow::Result<Vec<Element<'a>>> {
+        let page = match self.root {
+            Root::Page(id) => self.db.page(id)?,
+            Root::Inline(page) => page,
+        };
+
+        let mut elements = Vec::new();
+        self.walk(page, 0, &mut elements)?;
+
+        Ok(elements)
+    }
+
+    /// Collect the leaf elements of the tree rooted at `page`, in order.
+    fn walk(&self, page: &'a [u8], depth: usize, out: &mut Vec<Element<'a>>) -> anyhow::Result<()> {
+        if depth > MAX_DEPTH {
+            bail!("Corrupted bbolt database: tree deeper than {MAX_DEPTH} levels");
+        }
+
+        let flags = read_u16(page, 8).context("reading page flags")?;
+        let count = read_u16(page, 10).context("reading page count")? as usize;
+
+        for idx in 0..count {
+            if flags & BRANCH_PAGE_FLAG != 0 {
+                let (_, child) = branch_element(page, idx)?;
+                self.walk(self.db.page(child)?, depth + 1, out)?;
+            } else if flags & LEAF_PAGE_FLAG != 0 {
+                out.push(leaf_element(page, idx)?);
+            } else {
+                bail!("Unexpected bbolt page flags: {flags:#x}");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The `idx`-th element of a page, its offset and bytes.
+fn element(page: &[u8], idx: usize) -> anyhow::Result<(usize, &[u8])> {
+    let offset = PAGE_HEADER_SIZE + idx * ELEMENT_SIZE;
+    let element = page
+        .get(offset..offset + ELEMENT_SIZE)
+        .context("Page element out of bounds")?;
+
+    Ok((offset, element))
+}
+
+/// Key and child page id of a branch page's element.
+fn branch_element(page: &[u8], idx: usize) -> anyhow::Result<(&[u8], u64)> {
+    let (offset, element) = element(page, idx)?;
+    let pos = read_u32(element, 0).context("reading branch element")? as usize;
+    let ksize = read_u32(element, 4).context("reading branch element")? as usize;
+    let child = read_u64(element, 8).context("reading branch element")?;
+    // the position is relative to the element itself
+    let start = offset + pos;
+    let key = page
+        .get(start..start + ksize)
+        .context("Branch key out of bounds")?;
+
+    Ok((key, child))
+}
+
+/// Key/value pair of a leaf page's element.
+fn leaf_element(page: &[u8], idx: usize) -> anyhow::Result<Element<'_>> {
+    let (offset, element) = element(page, idx)?;
+    let pos = read_u32(element, 4).context("reading leaf element")? as usize;
+    let ksize = read_u32(element, 8).context("reading leaf element")? as usize;
+    let vsize = read_u32(element, 12).context("reading leaf element")? as usize;
+    // the position is relative to the element itself
+    let start = offset + pos;
+
+    Ok(Element {
+        flags: read_u32(element, 0).context("reading leaf element")?,
+        key: page
+            .get(start..start + ksize)
+            .context("Leaf key out of bounds")?,
+        value: page
+            .get(start + ksize..start + ksize + vsize)
+            .context("Leaf value out of bounds")?,
+    })
+}
+
+/// Binary search the `count` elements of a page, sorted by key, `compare` comparing an element's
+/// key to the searched one. Same result as [`slice::binary_search_by`].
+fn search(
+    count: usize,
+    compare: impl Fn(usize) -> anyhow::Result<Ordering>,
+) -> anyhow::Result<Result<usize, usize>> {
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        match compare(mid)? {
+            Ordering::Less => low = mid + 1,
+            Ordering::Greater => high = mid,
+            Ordering::Equal => return Ok(Ok(mid)),
+        }
+    }
+
+    Ok(Err(low))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+/// 64 bits FNV-1a hash, used by bbolt to checksum the meta pages.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// Fixtures generated by `tests/fixtures/bbolt.py`.
+    fn fixture(name: &str) -> Db {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name);
+        open(&path).unwrap()
+    }
+
+    fn key(id: u64) -> [u8; 8] {
+        id.to_be_bytes()
+    }
+
+    #[test]
+    fn reads_inline_bucket() {
+        let db = fixture("cliphist-inline.db");
+        let bucket = db.bucket(b"b").unwrap().unwrap();
+
+        let entries = bucket.entries().unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                (&key(1)[..], &b"hello"[..]),
+                (&key(2)[..], &b"foo\nbar"[..])
+            ]
+        );
+        assert_eq!(bucket.get(&key(2)).unwrap(), Some(&b"foo\nbar"[..]));
+        assert_eq!(bucket.get(&key(3)).unwrap(), None);
+    }
+
+    #[test]
+    fn looks_up_buckets() {
+        let db = fixture("cliphist-tree.db");
+
+        let inline = db.bucket(b"a").unwrap().unwrap();
+        assert_eq!(inline.get(b"k").unwrap(), Some(&b"v"[..]));
+        // plain values aren't buckets
+        assert!(db.bucket(b"c").unwrap().is_none());
+        assert!(db.bucket(b"missing").unwrap().is_none());
+    }
+
+    #[test]
+    fn walks_branch_pages() {
+        let db = fixture("cliphist-tree.db");
+        let bucket = db.bucket(b"b").unwrap().unwrap();
+
+        let keys = bucket
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|(k, _)| u64::from_be_bytes(k.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn gets_across_branch_pages() {
+        let db = fixture("cliphist-tree.db");
+        let bucket = db.bucket(b"b").unwrap().unwrap();
+
+        assert_eq!(bucket.get(&key(1)).unwrap(), Some(&b"hello"[..]));
+        assert_eq!(
+            bucket.get(&key(2)).unwrap(),
+            Some(&b"https://example.com"[..])
+        );
+        assert_eq!(bucket.get(&key(4)).unwrap(), Some(&b"world"[..]));
+        // before the first key, between leaves and after the last one
+        assert_eq!(bucket.get(&key(0)).unwrap(), None);
+        assert_eq!(bucket.get(&[0, 0, 0, 0, 0, 0, 0, 3, 1]).unwrap(), None);
+        assert_eq!(bucket.get(&key(6)).unwrap(), None);
+    }
+
+    #[test]
+    fn reads_overflow_values() {
+        let db = fixture("cliphist-tree.db");
+        let bucket = db.bucket(b"b").unwrap().unwrap();
+
+        let value = bucket.get(&key(5)).unwrap().unwrap();
+        assert_eq!(value.len(), 3000);
+        assert!(value.iter().all(|b| *b == b'x'));
+    }
+
+    #[test]
+    fn rejects_out_of_bounds_pages() {
+        let db = fixture("cliphist-tree.db");
+
+        assert!(db.page(9).is_err());
+        assert!(db.page(u64::MAX).is_err());
+        assert!(db.page(u64::MAX / db.page_size as u64).is_err());
+    }
+}
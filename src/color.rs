@@ -0,0 +1,468 @@
+use serde::{Deserialize, Serialize};
+
+use crate::cache::CacheEntry;
+
+/// Width and height of the swatch icons, rofi scales them to the icon size.
+const SWATCH_SIZE: u32 = 32;
+
+/// A color in the sRGB space, the components ranging from 0 to 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Color {
+    r: f64,
+    g: f64,
+    b: f64,
+    alpha: f64,
+}
+
+/// A color written in one of the supported notations.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Conversion {
+    /// Name of the notation, `hex`, `rgb`, `hsl` or `oklch`
+    pub notation: String,
+    pub value: String,
+}
+
+/// Parse a CSS color: `#rgb`, `#rgba`, `#rrggbb`, `#rrggbbaa`, `rgb()`, `rgba()`, `hsl()`,
+/// `hsla()` or `oklch()`, using either the comma or the space separated syntax.
+pub fn parse(text: &str) -> Option<Color> {
+    let text = text.trim().to_ascii_lowercase();
+    if let Some(hex) = text.strip_prefix('#') {
+        return parse_hex(hex);
+    }
+
+    let (function, args) = text.strip_suffix(')')?.split_once('(')?;
+    let args = args
+        .split([',', '/', ' '])
+        .filter(|arg| !arg.trim().is_empty())
+        .map(str::trim)
+        .collect::<Vec<_>>();
+    let alpha = match args.get(3) {
+        Some(alpha) => percent_or(alpha, 1.0)?,
+        None => 1.0,
+    };
+    if !(3..=4).contains(&args.len()) || !(0.0..=1.0).contains(&alpha) {
+        return None;
+    }
+
+    let color = match function.trim() {
+        "rgb" | "rgba" => Color {
+            r: percent_or(args[0], 255.0)?,
+            g: percent_or(args[1], 255.0)?,
+            b: percent_or(args[2], 255.0)?,
+            alpha,
+        },
+        "hsl" | "hsla" => {
+            let (r, g, b) = hsl_to_rgb(
+                hue(args[0])?,
+                percent_or(args[1], 100.0)?,
+                percent_or(args[2], 100.0)?,
+            );
+            Color { r, g, b, alpha }
+        }
+        "oklch" => {
+            // a 100% chroma is 0.4
+            let chroma = match args[1].strip_suffix('%') {
+                Some(percent) => percent.parse::<f64>().ok()? / 100.0 * 0.4,
+                None => args[1].parse::<f64>().ok()?,
+            };
+            let (r, g, b) = oklch_to_rgb(percent_or(args[0], 1.0)?, chroma, hue(args[2])?);
+            Color { r, g, b, alpha }
+        }
+        _ => return None,
+    };
+
+    let valid = [color.r, color.g, color.b]
+        .iter()
+        .all(|c| c.is_finite() && (-0.001..=1.001).contains(c));
+    valid.then(|| color.clamped())
+}
+
+impl Color {
+    /// The color written in every supported notation.
+    pub fn conversions(&self) -> Vec<Conversion> {
+        [
+            ("hex", self.hex()),
+            ("rgb", self.rgb()),
+            ("hsl", self.hsl()),
+            ("oklch", self.oklch()),
+        ]
+        .into_iter()
+        .map(|(notation, value)| Conversion {
+            notation: notation.into(),
+            value,
+        })
+        .collect()
+    }
+
+    /// `#rrggbb`, followed by the alpha when not opaque.
+    pub fn hex(&self) -> String {
+        let [r, g, b, alpha] = self.bytes();
+        match alpha {
+            255 => format!("#{r:02x}{g:02x}{b:02x}"),
+            _ => format!("#{r:02x}{g:02x}{b:02x}{alpha:02x}"),
+        }
+    }
+
+    /// `rgb(r, g, b)` or `rgba(r, g, b, alpha)`.
+    pub fn rgb(&self) -> String {
+        let [r, g, b, _] = self.bytes();
+        match self.alpha {
+            1.0 => format!("rgb({r}, {g}, {b})"),
+            alpha => format!("rgba({r}, {g}, {b}, {})", number(alpha, 2)),
+        }
+    }
+
+    /// `hsl(h, s%, l%)` or `hsla(h, s%, l%, alpha)`.
+    pub fn hsl(&self) -> String {
+        let max = self.r.max(self.g).max(self.b);
+        let min = self.r.min(self.g).min(self.b);
+        let delta = max - min;
+        let l = (max + min) / 2.0;
+        let s = match delta {
+            0.0 => 0.0,
+            _ => delta / (1.0 - (2.0 * l - 1.0).abs()),
+        };
+        // rounded first, so 359.6 wraps to 0 instead of showing 360
+        let h = hue_of(self.r, self.g, self.b, max, delta).round() % 360.0;
+
+        let (h, s, l) = (number(h, 0), number(s * 100.0, 0), number(l * 100.0, 0));
+        match self.alpha {
+            1.0 => format!("hsl({h}, {s}%, {l}%)"),
+            alpha => format!("hsla({h}, {s}%, {l}%, {})", number(alpha, 2)),
+        }
+    }
+
+    /// `oklch(l% c h)`, followed by `/ alpha` when not opaque.
+    pub fn oklch(&self) -> String {
+        let [r, g, b] = [self.r, self.g, self.b].map(to_linear);
+        let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+        let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+        let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+
+        let lightness = 0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s;
+        let a = 1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s;
+        let b = 0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s;
+        let chroma = (a * a + b * b).sqrt();
+        // grays have no meaningful hue, rounding errors would give a random one
+        let hue = match chroma < 0.0001 {
+            true => 0.0,
+            false => ((b.atan2(a).to_degrees() * 100.0).round() / 100.0).rem_euclid(360.0),
+        };
+
+        let color = format!(
+            "{}% {} {}",
+            number(lightness * 100.0, 2),
+            number(chroma, 4),
+            number(hue, 2)
+        );
+        match self.alpha {
+            1.0 => format!("oklch({color})"),
+            alpha => format!("oklch({color} / {})", number(alpha, 2)),
+        }
+    }
+
+    /// A square PNG image filled with the color.
+    pub fn swatch(&self) -> Vec<u8> {
+        // every scanline starts with its filter type, none
+        let mut scanline = vec![0];
+        scanline.extend(self.bytes().repeat(SWATCH_SIZE as usize));
+        let raw = scanline.repeat(SWATCH_SIZE as usize);
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&SWATCH_SIZE.to_be_bytes());
+        header.extend_from_slice(&SWATCH_SIZE.to_be_bytes());
+        // 8 bits RGBA, deflate, adaptive filtering, no interlace
+        header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png_chunk(&mut png, b"IHDR", &header);
+        png_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+        png_chunk(&mut png, b"IEND", &[]);
+
+        png
+    }
+
+    fn bytes(&self) -> [u8; 4] {
+        [self.r, self.g, self.b, self.alpha].map(|c| (c * 255.0).round() as u8)
+    }
+
+    fn clamped(self) -> Self {
+        Color {
+            r: self.r.clamp(0.0, 1.0),
+            g: self.g.clamp(0.0, 1.0),
+            b: self.b.clamp(0.0, 1.0),
+            alpha: self.alpha,
+        }
+    }
+}
+
+/// Swatches are keyed by the color, entries holding the same one share it.
+impl CacheEntry for Color {
+    fn id(&self) -> String {
+        let [r, g, b, alpha] = self.bytes();
+        format!("swatch-{r:02x}{g:02x}{b:02x}{alpha:02x}.png")
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<Color> {
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let digits = match hex.len() {
+        // shorthand, each digit is doubled
+        3 | 4 => hex.chars().flat_map(|c| [c, c]).collect::<String>(),
+        6 | 8 => hex.to_string(),
+        _ => return None,
+    };
+    let component = |at: usize| {
+        digits
+            .get(at..at + 2)
+            .and_then(|c| u8::from_str_radix(c, 16).ok())
+            .map(|c| c as f64 / 255.0)
+    };
+
+    Some(Color {
+        r: component(0)?,
+        g: component(2)?,
+        b: component(4)?,
+        alpha: component(6).unwrap_or(1.0),
+    })
+}
+
+/// A percentage or a number in the `0..=max` range, both scaled to `0..=1`.
+fn percent_or(arg: &str, max: f64) -> Option<f64> {
+    match arg.strip_suffix('%') {
+        Some(percent) => percent.parse::<f64>().ok().map(|p| p / 100.0),
+        None => arg.parse::<f64>().ok().map(|n| n / max),
+    }
+}
+
+/// An angle in degrees, the `deg` unit being optional.
+fn hue(arg: &str) -> Option<f64> {
+    let degrees = arg.strip_suffix("deg").unwrap_or(arg).parse::<f64>().ok()?;
+    degrees.is_finite().then(|| degrees.rem_euclid(360.0))
+}
+
+fn hue_of(r: f64, g: f64, b: f64, max: f64, delta: f64) -> f64 {
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        (g - b) / delta
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+
+    (hue * 60.0).rem_euclid(360.0)
+}
+
+fn hsl_to_rgb(h: f64, s: f64, l: f64) -> (f64, f64, f64) {
+    let channel = |n: f64| {
+        let k = (n + h / 30.0) % 12.0;
+        l - s * l.min(1.0 - l) * (k - 3.0).min(9.0 - k).clamp(-1.0, 1.0)
+    };
+
+    (channel(0.0), channel(8.0), channel(4.0))
+}
+
+fn oklch_to_rgb(lightness: f64, chroma: f64, hue: f64) -> (f64, f64, f64) {
+    let (a, b) = (
+        chroma * hue.to_radians().cos(),
+        chroma * hue.to_radians().sin(),
+    );
+    let l = (lightness + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+    let m = (lightness - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+    let s = (lightness - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+
+    let r = 4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s;
+    let g = -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s;
+    let b = -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s;
+
+    // out of gamut colors are clipped
+    let [r, g, b] = [r, g, b].map(|c| from_linear(c.clamp(0.0, 1.0)));
+    (r, g, b)
+}
+
+/// sRGB transfer function, from the gamma encoded value to the linear one.
+fn to_linear(c: f64) -> f64 {
+    match c <= 0.04045 {
+        true => c / 12.92,
+        false => ((c + 0.055) / 1.055).powf(2.4),
+    }
+}
+
+fn from_linear(c: f64) -> f64 {
+    match c <= 0.0031308 {
+        true => c * 12.92,
+        false => 1.055 * c.powf(1.0 / 2.4) - 0.055,
+    }
+}
+
+/// A number rounded to the given decimals, without trailing zeros.
+fn number(n: f64, decimals: usize) -> String {
+    let n = format!("{n:.decimals$}");
+    let n = match n.contains('.') {
+        true => n.trim_end_matches('0').trim_end_matches('.'),
+        false => &n,
+    };
+
+    match n {
+        "-0" => "0".to_string(),
+        n => n.to_string(),
+    }
+}
+
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Zlib stream of uncompressed deflate blocks, good enough for a few KiB of pixels.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(u16::MAX as usize).peekable();
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        stream.push(last as u8);
+        stream.extend_from_slice(&len.to_le_bytes());
+        stream.extend_from_slice(&(!len).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+
+    stream
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xedb8_8320,
+                _ => crc >> 1,
+            };
+        }
+    }
+
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> String {
+        parse(text).unwrap().hex()
+    }
+
+    #[test]
+    fn round_trips_hex() {
+        assert_eq!(hex("#1e1e2e"), "#1e1e2e");
+        assert_eq!(hex("#1E1E2E"), "#1e1e2e");
+        assert_eq!(hex("#abc"), "#aabbcc");
+        assert_eq!(hex("#11223344"), "#11223344");
+        assert_eq!(hex("#112233ff"), "#112233");
+        assert_eq!(parse("#12345"), None);
+        assert_eq!(parse("#ggg"), None);
+    }
+
+    #[test]
+    fn round_trips_rgb() {
+        assert_eq!(hex("rgb(30, 30, 46)"), "#1e1e2e");
+        assert_eq!(hex("rgb(100% 0% 50%)"), "#ff0080");
+        assert_eq!(parse("#1e1e2e").unwrap().rgb(), "rgb(30, 30, 46)");
+        let translucent = parse("rgb(30 30 46 / 50%)").unwrap();
+        assert_eq!(translucent.rgb(), "rgba(30, 30, 46, 0.5)");
+        assert_eq!(parse(&translucent.rgb()), Some(translucent));
+        assert_eq!(parse("rgb(30, 30)"), None);
+        assert_eq!(parse("rgb(300, 30, 46)"), None);
+    }
+
+    #[test]
+    fn round_trips_hsl() {
+        assert_eq!(parse("#ff0000").unwrap().hsl(), "hsl(0, 100%, 50%)");
+        assert_eq!(hex("hsl(0, 100%, 50%)"), "#ff0000");
+        assert_eq!(hex("hsl(120deg 100% 25%)"), "#008000");
+        assert_eq!(hex("hsla(240, 100%, 50%, 0.5)"), "#0000ff80");
+        for color in ["#1e1e2e", "#008000", "#808080"] {
+            assert_eq!(hex(&parse(color).unwrap().hsl()), color);
+        }
+        assert_eq!(parse("hsl(0, 100%, 50%, 2)"), None);
+    }
+
+    #[test]
+    fn round_trips_oklch() {
+        assert_eq!(parse("#ffffff").unwrap().oklch(), "oklch(100% 0 0)");
+        assert_eq!(parse("#000000").unwrap().oklch(), "oklch(0% 0 0)");
+        for color in ["#ff0000", "#1e1e2e", "#89b4fa", "#808080", "#12345678"] {
+            assert_eq!(hex(&parse(color).unwrap().oklch()), color);
+        }
+    }
+
+    #[test]
+    fn checksums() {
+        // check values of the CRC-32 and Adler-32 specifications
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        assert_eq!(adler32(b""), 1);
+    }
+
+    #[test]
+    fn writes_stored_zlib_blocks() {
+        let data = vec![7; u16::MAX as usize + 10];
+        let stream = zlib_stored(&data);
+
+        assert_eq!(&stream[..2], [0x78, 0x01]);
+        // a full block, then the last one
+        assert_eq!(&stream[2..7], [0, 0xff, 0xff, 0, 0]);
+        let second = 7 + u16::MAX as usize;
+        assert_eq!(&stream[second..second + 5], [1, 10, 0, 0xf5, 0xff]);
+        assert_eq!(stream.len(), second + 5 + 10 + 4);
+        assert_eq!(stream[stream.len() - 4..], adler32(&data).to_be_bytes());
+    }
+
+    #[test]
+    fn writes_png_swatches() {
+        let png = parse("#1e1e2e").unwrap().swatch();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+
+        // every chunk is checksummed, IEND last
+        let mut chunks = Vec::new();
+        let mut at = 8;
+        while at < png.len() {
+            let len = u32::from_be_bytes(png[at..at + 4].try_into().unwrap()) as usize;
+            let (kind, data) = (&png[at + 4..at + 8], &png[at + 8..at + 8 + len]);
+            let crc = &png[at + 8 + len..at + 12 + len];
+            assert_eq!(crc, crc32(&png[at + 4..at + 8 + len]).to_be_bytes());
+            chunks.push((kind.to_vec(), data.to_vec()));
+            at += 12 + len;
+        }
+        assert_eq!(at, png.len());
+        let kinds = chunks.iter().map(|(kind, _)| &kind[..]).collect::<Vec<_>>();
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 32, 0, 0, 0, 32, 8, 6, 0, 0, 0]);
+
+        // a filter byte and 32 pixels per scanline
+        let mut scanline = vec![0];
+        scanline.extend([0x1e, 0x1e, 0x2e, 0xff].repeat(32));
+        assert_eq!(chunks[1].1, zlib_stored(&scanline.repeat(32)));
+    }
+}
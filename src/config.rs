@@ -1,19 +1,41 @@
-use anyhow::Context;
+use anyhow::{bail, Context};
 use log::debug;
-use std::{fs, path::PathBuf};
+use std::{collections::HashMap, fs, path::PathBuf};
 
 use directories_next::{self, BaseDirs};
 use serde::{Deserialize, Serialize};
+use toml::Spanned;
+
+use crate::{
+    action::{Action, MAX_CUSTOM_KBS},
+    history, launcher,
+    snippet::Snippet,
+    transform::Filter,
+};
 
 /// CLI configuration
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Config {
+    #[serde(default)]
+    pub launcher: Launcher,
     #[serde(default)]
     pub rofi: Rofi,
     #[serde(default)]
+    pub fzf: Fzf,
+    #[serde(default)]
+    pub history: History,
+    #[serde(default)]
     pub cliphist: ClipHist,
     #[serde(default)]
     pub clipboard: Clipboard,
+    #[serde(default)]
+    pub transform: Transform,
+    #[serde(default)]
+    pub open: Open,
+    #[serde(default)]
+    pub secrets: Secrets,
+    #[serde(default)]
+    pub retention: Retention,
     #[serde(default = "default_image_mode_config")]
     pub image_mode_config: ModeConfig,
     #[serde(default = "default_text_mode_config")]
@@ -24,6 +46,22 @@ pub struct Config {
     pub delete_previous_config: ModeConfig,
     #[serde(default = "default_delete_next_config")]
     pub delete_next_config: ModeConfig,
+    /// Custom shortcuts, replacing the ones of the `*_config` sections and the default ones when
+    /// not empty
+    #[serde(default)]
+    pub keybindings: Vec<Keybinding>,
+    /// Directory of snippets, one per file, defaults to `$XDG_CONFIG_HOME/rofi-cliphist/snippets`
+    pub snippets_dir: Option<PathBuf>,
+    /// Snippets listed before the ones of `snippets_dir`
+    #[serde(default)]
+    pub snippets: Vec<Snippet>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Launcher {
+    pub kind: launcher::Kind,
+    /// Path to the launcher executable, defaults to the kind's name (or `rofi.path` for rofi)
+    pub path: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -31,29 +69,231 @@ pub struct Rofi {
     pub path: String,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Fzf {
+    /// Command previewing text entries, `{id}` being replaced by the entry's id. Defaults to
+    /// `rofi-cliphist get {id}`, with the same config file and backend
+    pub text_preview: Option<String>,
+    /// Command previewing image entries, `{file}` being replaced by the cached image
+    #[serde(default = "default_image_preview")]
+    pub image_preview: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct History {
+    pub backend: history::Kind,
+    /// Path to clipman's history, defaults to `$XDG_DATA_HOME/clipman.json`
+    pub clipman_path: Option<PathBuf>,
+    /// Path to the built-in store, defaults to `$XDG_DATA_HOME/roto/history`
+    pub store_path: Option<PathBuf>,
+    /// Path to the pinned entries, defaults to `$XDG_DATA_HOME/roto/pinned`
+    pub pinned_path: Option<PathBuf>,
+    /// Entries `roto-clipd` keeps in the built-in store, the oldest ones being removed
+    pub max_entries: usize,
+    /// Bigger entries are ignored by `roto-clipd`, in bytes
+    pub max_entry_size: u64,
+    /// Whether texts only differing by leading and trailing whitespace are listed once, along with
+    /// their count
+    pub dedupe: bool,
+    /// Whether the full content of the texts the previews cut off is searched, indexed in
+    /// `$XDG_CACHE_HOME/rofi-cliphist/index.json`
+    pub full_text_search: bool,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ClipHist {
     pub path: String,
+    /// How entries are read, deletes always go through the cliphist executable
+    #[serde(default)]
+    pub reader: ClipHistReader,
+    /// Path to cliphist's database, defaults to `$XDG_CACHE_HOME/cliphist/db`
+    pub db_path: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipHistReader {
+    /// Spawn `cliphist list` and `cliphist decode`
+    #[default]
+    Cli,
+    /// Read cliphist's database file directly
+    Db,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Clipboard {
     pub path: String,
+    /// Separator used to join several text entries copied at once
+    #[serde(default = "default_separator")]
+    pub separator: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Transform {
+    /// Whether transformed texts are also stored as new history entries
+    pub store: bool,
+    /// Commands listed after the built-in transforms
+    pub filters: Vec<Filter>,
+}
+
+/// Rules pruning the history, enforced at startup and by `rofi-cliphist prune`
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Retention {
+    /// Text entries kept, the oldest ones being deleted
+    pub max_texts: Option<usize>,
+    /// Image entries kept, the oldest ones being deleted
+    pub max_images: Option<usize>,
+    /// Other binary entries kept, the oldest ones being deleted
+    pub max_files: Option<usize>,
+    /// Total size of the history in bytes, the oldest entries beyond it being deleted
+    pub max_bytes: Option<u64>,
+    /// Days an entry is kept since first copied, for the backends recording it (the store)
+    pub max_age_days: Option<u64>,
+    /// Regular expressions, the text entries whose content matches are deleted
+    pub exclude: Vec<String>,
+}
+
+/// Detection of passwords and tokens, masked in the list and deleted after a while
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct Secrets {
+    pub enabled: bool,
+    /// Seconds a secret is kept in the history once first seen, forever (only masked) when 0, the
+    /// default
+    pub ttl: u64,
+    /// Regular expressions matched against the entries' content, besides the built-in ones
+    pub patterns: Vec<String>,
+    /// Path to the secrets' countdowns, defaults to `$XDG_DATA_HOME/roto/secrets`. The checked
+    /// texts are saved next to it, with a `.checked` extension
+    pub path: Option<PathBuf>,
+}
+
+/// Commands opening entries, run by `sh -c` once the placeholder is replaced by the shell quoted
+/// value
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Open {
+    /// Command opening URLs and e-mail addresses, `{url}` being replaced by the URL
+    pub url: String,
+    /// Command opening directories, `{path}` being replaced by their path
+    pub directory: String,
+    /// Command opening other files, `{path}` being replaced by their path
+    pub file: String,
+    /// Command opening images, `{file}` being replaced by a temporary copy
+    pub image: String,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ModeConfig {
     pub title: String,
-    pub shortcut: String,
+    pub shortcut: Spanned<String>,
     pub description: String,
 }
 
+/// A shortcut bound to one of the built-in actions
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Keybinding {
+    pub shortcut: Spanned<String>,
+    pub action: Action,
+    pub description: Option<String>,
+}
+
+impl Keybinding {
+    /// Description shown in the shortcuts help, the action's default one if not set.
+    pub fn description(&self) -> String {
+        self.description
+            .clone()
+            .unwrap_or_else(|| self.action.description().to_string())
+    }
+}
+
+impl Config {
+    /// Custom shortcuts, either the `[[keybindings]]` table or the ones of the `*_config`
+    /// sections followed by the default ones of the other actions.
+    pub fn keybindings(&self) -> Vec<Keybinding> {
+        if !self.keybindings.is_empty() {
+            return self.keybindings.clone();
+        }
+
+        [
+            (Action::ImageMode, &self.image_mode_config),
+            (Action::TextMode, &self.text_mode_config),
+            (Action::Delete, &self.delete_mode_config),
+            (Action::DeletePrevious, &self.delete_previous_config),
+            (Action::DeleteNext, &self.delete_next_config),
+        ]
+        .into_iter()
+        .map(|(action, mode)| Keybinding {
+            shortcut: mode.shortcut.clone(),
+            action,
+            description: Some(mode.description.clone()),
+        })
+        .chain(default_keybindings())
+        .collect()
+    }
+}
+
 /// Load configuration from a file
 pub fn load(path: &PathBuf) -> anyhow::Result<Config> {
     debug!("Loading config from file: {path:?}");
 
-    let config = fs::read_to_string(path).context("Error reading config file")?;
-    toml::from_str(&config).context("Error parsing config file")
+    let source = fs::read_to_string(path).context("Error reading config file")?;
+    let config = toml::from_str(&source).context(format!("Error parsing config file {path:?}"))?;
+    validate(&config, &source).context(format!("Invalid config file {path:?}"))?;
+
+    Ok(config)
+}
+
+/// Validate what the parser can't, pointing to the offending line of `source`.
+///
+/// The resolved keybindings are checked, so the shortcuts of the `*_config` sections can't collide
+/// either.
+fn validate(config: &Config, source: &str) -> anyhow::Result<()> {
+    // the defaults aren't written in the file
+    let line = |kb: &Keybinding| {
+        let span = kb.shortcut.span();
+        (!span.is_empty()).then(|| line_of(source, span.start))
+    };
+
+    let keybindings = config.keybindings();
+    let mut shortcuts: HashMap<String, &Keybinding> = HashMap::new();
+    for (idx, kb) in keybindings.iter().enumerate() {
+        if idx >= MAX_CUSTOM_KBS {
+            let at = line(kb)
+                .map(|line| format!("line {line}: "))
+                .unwrap_or_default();
+            bail!("{at}too many keybindings, rofi supports up to {MAX_CUSTOM_KBS}");
+        }
+        // rofi doesn't care about the case of the modifiers and keys
+        let Some(first) = shortcuts.insert(kb.shortcut.get_ref().to_lowercase(), kb) else {
+            continue;
+        };
+        let shortcut = kb.shortcut.get_ref();
+        match (line(first), line(kb)) {
+            (Some(first), Some(line)) => {
+                bail!("line {line}: duplicate shortcut '{shortcut}', already bound at line {first}")
+            }
+            (Some(line), None) => bail!(
+                "line {line}: shortcut '{shortcut}' is bound to '{}' by default",
+                kb.description()
+            ),
+            (None, Some(line)) => bail!(
+                "line {line}: shortcut '{shortcut}' is bound to '{}' by default",
+                first.description()
+            ),
+            (None, None) => bail!("duplicate default shortcut '{shortcut}'"),
+        }
+    }
+
+    Ok(())
+}
+
+/// 1-based line number of a byte offset.
+fn line_of(source: &str, offset: usize) -> usize {
+    source[..offset.min(source.len())].matches('\n').count() + 1
 }
 
 /// Load the default configuration
@@ -70,26 +310,33 @@ impl Default for Config {
     fn default() -> Self {
         debug!("Creating default config");
         Self {
+            launcher: Launcher::default(),
             rofi: Rofi::default(),
+            fzf: Fzf::default(),
+            history: History::default(),
             cliphist: ClipHist::default(),
             clipboard: Clipboard::default(),
-            image_mode_config: ModeConfig {
-                title: "Images".to_string(),
-                shortcut: "Alt+i".to_string(),
-                description: "Switch to images".to_string(),
-            },
-            text_mode_config: ModeConfig {
-                title: "Texts".to_string(),
-                shortcut: "Alt+t".to_string(),
-                description: "Switch to text".to_string(),
-            },
-            delete_mode_config: ModeConfig {
-                title: "Delete".to_string(),
-                shortcut: "Alt+d".to_string(),
-                description: "Delete entry".to_string(),
-            },
+            transform: Transform::default(),
+            open: Open::default(),
+            secrets: Secrets::default(),
+            retention: Retention::default(),
+            image_mode_config: default_image_mode_config(),
+            text_mode_config: default_text_mode_config(),
+            delete_mode_config: default_delete_mode_config(),
             delete_previous_config: default_delete_previous_config(),
             delete_next_config: default_delete_next_config(),
+            keybindings: vec![],
+            snippets_dir: None,
+            snippets: vec![],
+        }
+    }
+}
+
+impl Default for Launcher {
+    fn default() -> Self {
+        Self {
+            kind: launcher::Kind::Rofi,
+            path: None,
         }
     }
 }
@@ -102,10 +349,60 @@ impl Default for Rofi {
     }
 }
 
+impl Default for Fzf {
+    fn default() -> Self {
+        Self {
+            text_preview: None,
+            image_preview: default_image_preview(),
+        }
+    }
+}
+
+impl Default for Secrets {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ttl: 0,
+            patterns: vec![],
+            path: None,
+        }
+    }
+}
+
+impl Default for Open {
+    fn default() -> Self {
+        Self {
+            url: "xdg-open {url}".to_string(),
+            directory: "xdg-open {path}".to_string(),
+            // editors usually run in a terminal, so both are needed
+            file: r#"if [ -n "$EDITOR" ] && [ -n "$TERMINAL" ]; then $TERMINAL -e $EDITOR {path}; else xdg-open {path}; fi"#
+                .to_string(),
+            image: "xdg-open {file}".to_string(),
+        }
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            backend: history::Kind::Cliphist,
+            clipman_path: None,
+            store_path: None,
+            pinned_path: None,
+            max_entries: default_max_entries(),
+            max_entry_size: default_max_entry_size(),
+            dedupe: false,
+            full_text_search: false,
+        }
+    }
+}
+
 impl Default for ClipHist {
     fn default() -> Self {
         Self {
             path: "cliphist".to_string(),
+            reader: ClipHistReader::default(),
+            db_path: None,
         }
     }
 }
@@ -114,6 +411,7 @@ impl Default for Clipboard {
     fn default() -> Self {
         Self {
             path: "wl-copy".to_string(),
+            separator: default_separator(),
         }
     }
 }
@@ -121,7 +419,7 @@ impl Default for Clipboard {
 fn default_image_mode_config() -> ModeConfig {
     ModeConfig {
         title: "Images".to_string(),
-        shortcut: "Alt+i".to_string(),
+        shortcut: shortcut("Alt+i"),
         description: "Switch to images".to_string(),
     }
 }
@@ -129,7 +427,7 @@ fn default_image_mode_config() -> ModeConfig {
 fn default_text_mode_config() -> ModeConfig {
     ModeConfig {
         title: "Texts".to_string(),
-        shortcut: "Alt+t".to_string(),
+        shortcut: shortcut("Alt+t"),
         description: "Switch to text".to_string(),
     }
 }
@@ -137,7 +435,7 @@ fn default_text_mode_config() -> ModeConfig {
 fn default_delete_mode_config() -> ModeConfig {
     ModeConfig {
         title: "Delete".to_string(),
-        shortcut: "Alt+d".to_string(),
+        shortcut: shortcut("Alt+d"),
         description: "Delete entry".to_string(),
     }
 }
@@ -145,7 +443,7 @@ fn default_delete_mode_config() -> ModeConfig {
 fn default_delete_previous_config() -> ModeConfig {
     ModeConfig {
         title: "Delete previous".to_string(),
-        shortcut: "Alt+p".to_string(),
+        shortcut: shortcut("Alt+p"),
         description: "Delete all entries before the selected one".to_string(),
     }
 }
@@ -153,7 +451,116 @@ fn default_delete_previous_config() -> ModeConfig {
 fn default_delete_next_config() -> ModeConfig {
     ModeConfig {
         title: "Delete next".to_string(),
-        shortcut: "Alt+n".to_string(),
+        shortcut: shortcut("Alt+n"),
         description: "Delete all entries after the selected one".to_string(),
     }
 }
+
+/// Shortcuts of the actions without a `*_config` section, in the order of their `kb-custom-N`
+/// slots after the sections' ones.
+fn default_keybindings() -> Vec<Keybinding> {
+    [
+        ("Alt+o", Action::BinaryMode),
+        ("Alt+s", Action::Sort),
+        ("Alt+m", Action::PinnedMode),
+        ("Alt+k", Action::Pin),
+        ("Alt+u", Action::Unpin),
+        ("Alt+Up", Action::MoveUp),
+        ("Alt+Down", Action::MoveDown),
+        ("Alt+e", Action::SnippetsMode),
+        ("Alt+x", Action::Transform),
+        ("Alt+c", Action::ConvertColor),
+        ("Alt+Return", Action::Open),
+        ("Alt+g", Action::Dedupe),
+    ]
+    .into_iter()
+    .map(|(key, action)| Keybinding {
+        shortcut: shortcut(key),
+        action,
+        description: None,
+    })
+    .collect()
+}
+
+/// Default shortcut, not written in the config file.
+fn shortcut(shortcut: &str) -> Spanned<String> {
+    Spanned::new(0..0, shortcut.to_string())
+}
+
+fn default_max_entries() -> usize {
+    750
+}
+
+fn default_max_entry_size() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_separator() -> String {
+    "\n".to_string()
+}
+
+fn default_image_preview() -> String {
+    "chafa --size=${FZF_PREVIEW_COLUMNS}x${FZF_PREVIEW_LINES} {file}".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validated(source: &str) -> anyhow::Result<Config> {
+        let config = toml::from_str(source)?;
+        validate(&config, source)?;
+        Ok(config)
+    }
+
+    #[test]
+    fn accepts_the_defaults() {
+        let config = validated("").unwrap();
+        assert!(config.keybindings().len() <= MAX_CUSTOM_KBS);
+    }
+
+    #[test]
+    fn reports_duplicate_keybindings() {
+        let source = r#"
+[[keybindings]]
+shortcut = "Alt+d"
+action = "delete"
+
+[[keybindings]]
+shortcut = "alt+D"
+action = "copy"
+"#;
+        let e = validated(source).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "line 7: duplicate shortcut 'alt+D', already bound at line 3"
+        );
+    }
+
+    #[test]
+    fn reports_duplicate_legacy_shortcuts() {
+        let source = r#"
+[delete_mode_config]
+title = "Delete"
+shortcut = "Alt+o"
+description = "Delete entry"
+"#;
+        let e = validated(source).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "line 4: shortcut 'Alt+o' is bound to 'Switch to files' by default"
+        );
+    }
+
+    #[test]
+    fn reports_too_many_keybindings() {
+        let source = (0..=MAX_CUSTOM_KBS)
+            .map(|n| format!("[[keybindings]]\nshortcut = \"Alt+{n}\"\naction = \"copy\"\n"))
+            .collect::<String>();
+        let e = validated(&source).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "line 59: too many keybindings, rofi supports up to 19"
+        );
+    }
+}
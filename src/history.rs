@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::cliphist::{ClipHist, ClipHistEntry};
+
+pub mod clipman;
+pub mod pinned;
+pub mod store;
+
+/// What a backend records about an entry, besides its content.
+#[derive(Debug, Clone, Copy)]
+pub struct Meta {
+    /// Size in bytes
+    pub size: u64,
+    /// Unix timestamp of the first time the entry was copied
+    pub first_seen: u64,
+}
+
+/// A clipboard history the entries are read from and written to.
+///
+/// Ids are opaque to the callers, they only have to be stable while the history isn't modified.
+pub trait HistoryBackend {
+    /// All the entries, newest first.
+    fn list(&self) -> anyhow::Result<Vec<ClipHistEntry>>;
+
+    /// The full content of an entry.
+    fn decode(&self, id: &str) -> anyhow::Result<Vec<u8>>;
+
+    /// Remove an entry.
+    fn delete(&self, id: &str) -> anyhow::Result<()>;
+
+    /// Remove several entries, returning the ids that were actually removed.
+    fn delete_many(&self, ids: &[String]) -> anyhow::Result<Vec<String>> {
+        let mut deleted = Vec::with_capacity(ids.len());
+        for id in ids {
+            match self.delete(id) {
+                Ok(()) => deleted.push(id.clone()),
+                Err(e) => warn!("Error deleting entry {id}: {e:?}"),
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    /// Add a new entry, becoming the newest one.
+    fn store(&self, content: Vec<u8>) -> anyhow::Result<()>;
+
+    /// Metadata of the entries by id, empty if the backend doesn't record any.
+    fn meta(&self) -> anyhow::Result<HashMap<String, Meta>> {
+        Ok(HashMap::new())
+    }
+}
+
+/// Supported history backends
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Cliphist,
+    Clipman,
+    Store,
+}
+
+impl HistoryBackend for ClipHist {
+    fn list(&self) -> anyhow::Result<Vec<ClipHistEntry>> {
+        ClipHist::list(self)
+    }
+
+    fn decode(&self, id: &str) -> anyhow::Result<Vec<u8>> {
+        self.value_of(id.into())
+    }
+
+    fn delete(&self, id: &str) -> anyhow::Result<()> {
+        self.remove(id.into())
+    }
+
+    fn delete_many(&self, ids: &[String]) -> anyhow::Result<Vec<String>> {
+        self.remove_many(ids)
+    }
+
+    fn store(&self, content: Vec<u8>) -> anyhow::Result<()> {
+        ClipHist::store(self, content)
+    }
+}
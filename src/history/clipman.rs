@@ -0,0 +1,192 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{bail, Context};
+use directories_next::BaseDirs;
+use log::{debug, trace};
+use sha2::{Digest, Sha256};
+
+use crate::cliphist::{self, ClipHistEntry};
+
+use super::HistoryBackend;
+
+/// History kept by [clipman](https://github.com/chmouel/clipman): a JSON array of strings, the
+/// oldest first.
+///
+/// clipman only stores text and its entries have no ids, so they are identified by a hash of their
+/// content, which stays valid when other entries are removed.
+pub struct Clipman {
+    path: PathBuf,
+}
+
+/// Create a clipman backend reading the given history file.
+pub fn new(path: impl Into<PathBuf>) -> Clipman {
+    trace!("Creating clipman backend");
+    Clipman { path: path.into() }
+}
+
+/// Default location of clipman's history, `$XDG_DATA_HOME/clipman.json`.
+pub fn default_path() -> anyhow::Result<PathBuf> {
+    let dirs = BaseDirs::new().context("Error getting base directories")?;
+
+    Ok(dirs.data_dir().join("clipman.json"))
+}
+
+impl Clipman {
+    fn read(&self) -> anyhow::Result<Vec<String>> {
+        if !self.path.exists() {
+            return Ok(vec![]);
+        }
+
+        let source = fs::read(&self.path).context(format!("Error reading {:?}", self.path))?;
+        serde_json::from_slice(&source).context(format!("Error parsing {:?}", self.path))
+    }
+
+    /// Replace the history file atomically, clipman could be reading it.
+    fn write(&self, history: &[String]) -> anyhow::Result<()> {
+        let tmp = self.path.with_extension("json.tmp");
+
+        let json = serde_json::to_vec(history).context("Error serializing clipman history")?;
+        fs::write(&tmp, json).context(format!("Error writing {tmp:?}"))?;
+        fs::rename(&tmp, &self.path).context(format!("Error writing {:?}", self.path))
+    }
+}
+
+impl HistoryBackend for Clipman {
+    fn list(&self) -> anyhow::Result<Vec<ClipHistEntry>> {
+        trace!("Listing clipman history");
+        let history = self
+            .read()?
+            .iter()
+            .rev()
+            .map(|text| cliphist::entry(id(text), text.as_bytes()))
+            .collect::<Vec<_>>();
+
+        debug!("Found {} entries in clipman history", history.len());
+
+        Ok(history)
+    }
+
+    fn decode(&self, id: &str) -> anyhow::Result<Vec<u8>> {
+        self.read()?
+            .into_iter()
+            .find(|text| self::id(text) == id)
+            .map(String::into_bytes)
+            .context(format!("Entry {id} not found in clipman history"))
+    }
+
+    fn delete(&self, id: &str) -> anyhow::Result<()> {
+        let mut history = self.read()?;
+        let before = history.len();
+        history.retain(|text| self::id(text) != id);
+        if history.len() == before {
+            bail!("Entry {id} not found in clipman history");
+        }
+
+        debug!("Removing entry with id: {id}");
+        self.write(&history)
+    }
+
+    fn delete_many(&self, ids: &[String]) -> anyhow::Result<Vec<String>> {
+        let mut history = self.read()?;
+        let mut removed = Vec::new();
+        history.retain(|text| {
+            let id = self::id(text);
+            let keep = !ids.contains(&id);
+            if !keep {
+                removed.push(id);
+            }
+            keep
+        });
+
+        if !removed.is_empty() {
+            debug!("Removing {} entries", removed.len());
+            self.write(&history)?;
+        }
+
+        Ok(removed)
+    }
+
+    fn store(&self, content: Vec<u8>) -> anyhow::Result<()> {
+        let Ok(text) = String::from_utf8(content) else {
+            bail!("clipman only stores text");
+        };
+
+        // like clipman, a duplicate is moved to the top
+        let mut history = self.read()?;
+        history.retain(|t| *t != text);
+        history.push(text);
+
+        trace!("Storing new entry");
+        self.write(&history)
+    }
+}
+
+/// Id of a clipman entry, the first 16 hex digits of the SHA-256 of its text.
+fn id(text: &str) -> String {
+    format!("{:x}", Sha256::digest(text.as_bytes()))[..16].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rofi::RofiEntry;
+
+    fn titles(clipman: &Clipman) -> Vec<String> {
+        clipman
+            .list()
+            .unwrap()
+            .iter()
+            .map(RofiEntry::label)
+            .collect()
+    }
+
+    #[test]
+    fn reads_clipman_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clipman.json");
+        fs::write(&path, r#"["oldest", "multi\nline", "newest"]"#).unwrap();
+        let clipman = new(&path);
+
+        let entries = clipman.list().unwrap();
+        assert_eq!(titles(&clipman), vec!["newest", "multi line", "oldest"]);
+        assert_eq!(RofiEntry::id(&entries[1]), id("multi\nline"));
+        assert_eq!(
+            clipman.decode(&RofiEntry::id(&entries[1])).unwrap(),
+            b"multi\nline"
+        );
+        assert!(clipman.decode("0123456789abcdef").is_err());
+    }
+
+    #[test]
+    fn reads_a_missing_history_as_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let clipman = new(dir.path().join("clipman.json"));
+
+        assert!(clipman.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn stores_and_deletes_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clipman.json");
+        let clipman = new(&path);
+
+        for text in ["a", "b", "c", "a"] {
+            clipman.store(text.as_bytes().to_vec()).unwrap();
+        }
+        // a duplicate is moved to the top, like clipman does
+        assert_eq!(titles(&clipman), vec!["a", "c", "b"]);
+        assert!(clipman.store(vec![0xff, 0xfe]).is_err());
+
+        let removed = clipman
+            .delete_many(&[id("b"), id("c"), "nope".into()])
+            .unwrap();
+        assert_eq!(removed, vec![id("b"), id("c")]);
+        assert!(clipman.delete(&id("b")).is_err());
+        clipman.delete(&id("a")).unwrap();
+
+        // still a plain JSON array clipman can read
+        let source = fs::read_to_string(&path).unwrap();
+        assert_eq!(source, "[]");
+    }
+}
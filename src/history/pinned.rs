@@ -0,0 +1,339 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::PathBuf,
+};
+
+use anyhow::Context;
+use directories_next::BaseDirs;
+use log::{debug, trace, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{cliphist::ClipHistEntry, rofi::RofiEntry};
+
+use super::{
+    store::{self, Store},
+    HistoryBackend,
+};
+
+/// Prefix of the pinned entries' ids, telling them apart from the history ones.
+const ID_PREFIX: &str = "pin-";
+/// File keeping the digests of the history entries looking like a pinned one.
+const DIGESTS_FILE: &str = "digests.json";
+
+/// Entries pinned by the user, copied to a store of their own so they survive history wipes and
+/// deletions.
+///
+/// Their ids are prefixed with `pin-`, so they never clash with the history's (e.g. in the
+/// thumbnails cache). The list is ordered by the user, the top entry first.
+pub struct Pinned {
+    store: Store,
+    digests: PathBuf,
+}
+
+/// SHA-256 of a history entry's content, along with the key it was computed for, so a reused id
+/// isn't mistaken for the same entry.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Hashed {
+    key: String,
+    digest: String,
+}
+
+/// Create the pinned entries store located in `dir`.
+pub fn new(dir: impl Into<PathBuf>) -> Pinned {
+    trace!("Creating pinned entries store");
+    let dir = dir.into();
+    Pinned {
+        digests: dir.join(DIGESTS_FILE),
+        store: store::new(dir),
+    }
+}
+
+/// Default location of the pinned entries, `$XDG_DATA_HOME/roto/pinned`.
+pub fn default_path() -> anyhow::Result<PathBuf> {
+    let dirs = BaseDirs::new().context("Error getting base directories")?;
+
+    Ok(dirs.data_dir().join("roto").join("pinned"))
+}
+
+/// Whether the id belongs to a pinned entry.
+pub fn is_pinned(id: &str) -> bool {
+    id.starts_with(ID_PREFIX)
+}
+
+impl Pinned {
+    /// Move a pinned entry one position up or down.
+    pub fn shift(&self, id: &str, up: bool) -> anyhow::Result<()> {
+        self.store.shift(store_id(id)?, up)
+    }
+
+    /// Ids of the history entries whose content is pinned, so they're only listed once.
+    ///
+    /// Only the entries looking like a pinned one are decoded, and only once: the SHA-256 of their
+    /// content is saved next to the pinned entries, the ones gone from the history being forgotten.
+    pub fn copies(
+        &self,
+        pinned: &[ClipHistEntry],
+        history: &dyn HistoryBackend,
+        entries: &[ClipHistEntry],
+    ) -> anyhow::Result<HashSet<String>> {
+        if pinned.is_empty() {
+            return Ok(HashSet::new());
+        }
+        let keys = pinned.iter().map(content_key).collect::<HashSet<_>>();
+        let hashes = self
+            .store
+            .records()?
+            .into_iter()
+            .map(|record| record.hash)
+            .collect::<HashSet<_>>();
+
+        let saved = self.read_digests();
+        let mut digests = BTreeMap::new();
+        let mut copies = HashSet::new();
+        for entry in entries {
+            let key = content_key(entry);
+            if !keys.contains(&key) {
+                continue;
+            }
+            let id = RofiEntry::id(entry);
+            let hashed = match saved.get(&id) {
+                Some(hashed) if hashed.key == key => hashed.clone(),
+                _ => match history.decode(&id) {
+                    Ok(content) => Hashed {
+                        key,
+                        digest: format!("{:x}", Sha256::digest(content)),
+                    },
+                    Err(e) => {
+                        warn!("Error getting history entry {id}: {e:?}");
+                        continue;
+                    }
+                },
+            };
+            if hashes.contains(&hashed.digest) {
+                copies.insert(id.clone());
+            }
+            digests.insert(id, hashed);
+        }
+        debug!("Found {} pinned entries in the history", copies.len());
+
+        if digests != saved {
+            self.write_digests(&digests)?;
+        }
+
+        Ok(copies)
+    }
+
+    fn read_digests(&self) -> BTreeMap<String, Hashed> {
+        let Ok(source) = fs::read(&self.digests) else {
+            return BTreeMap::new();
+        };
+
+        // corrupted digests are computed again
+        serde_json::from_slice(&source).unwrap_or_else(|e| {
+            warn!("Error parsing {:?}, rebuilding it: {e}", self.digests);
+            BTreeMap::new()
+        })
+    }
+
+    fn write_digests(&self, digests: &BTreeMap<String, Hashed>) -> anyhow::Result<()> {
+        if let Some(dir) = self.digests.parent() {
+            fs::create_dir_all(dir).context(format!("Error creating {dir:?}"))?;
+        }
+        let json = serde_json::to_vec(digests).context("Error serializing digests")?;
+
+        fs::write(&self.digests, json).context(format!("Error writing {:?}", self.digests))
+    }
+}
+
+impl HistoryBackend for Pinned {
+    fn list(&self) -> anyhow::Result<Vec<ClipHistEntry>> {
+        let entries = self
+            .store
+            .records()?
+            .into_iter()
+            .map(|record| match ClipHistEntry::from(record) {
+                ClipHistEntry::Text {
+                    id,
+                    title,
+                    category,
+                } => ClipHistEntry::Text {
+                    id: format!("{ID_PREFIX}{id}"),
+                    title,
+                    category,
+                },
+                ClipHistEntry::Image {
+                    id,
+                    content_type,
+                    size,
+                    dimensions,
+                } => ClipHistEntry::Image {
+                    id: format!("{ID_PREFIX}{id}"),
+                    content_type,
+                    size,
+                    dimensions,
+                },
+                ClipHistEntry::Binary { id, mime, size } => ClipHistEntry::Binary {
+                    id: format!("{ID_PREFIX}{id}"),
+                    mime,
+                    size,
+                },
+            })
+            .collect();
+
+        Ok(entries)
+    }
+
+    fn decode(&self, id: &str) -> anyhow::Result<Vec<u8>> {
+        self.store.read(store_id(id)?)
+    }
+
+    fn delete(&self, id: &str) -> anyhow::Result<()> {
+        self.store.remove(store_id(id)?)
+    }
+
+    fn store(&self, content: Vec<u8>) -> anyhow::Result<()> {
+        self.store.insert(&content, None).map(|_| ())
+    }
+}
+
+/// Id of the entry in the underlying store.
+fn store_id(id: &str) -> anyhow::Result<u64> {
+    id.strip_prefix(ID_PREFIX)
+        .and_then(|id| id.parse().ok())
+        .context(format!("Invalid pinned entry id: {id}"))
+}
+
+/// Key shared by the entries that may have the same content: same preview, image dimensions or
+/// file type. Histories may size entries approximately, so sizes aren't part of it.
+fn content_key(entry: &ClipHistEntry) -> String {
+    match entry {
+        ClipHistEntry::Text { title, .. } => format!("text:{title}"),
+        ClipHistEntry::Image { dimensions, .. } => format!("image:{dimensions:?}"),
+        ClipHistEntry::Binary { mime, .. } => format!("file:{mime}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    fn ids(entries: &[ClipHistEntry]) -> Vec<String> {
+        entries.iter().map(RofiEntry::id).collect()
+    }
+
+    #[test]
+    fn prefixes_the_store_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let pinned = new(dir.path());
+        pinned.store(b"a".to_vec()).unwrap();
+        pinned.store(b"b".to_vec()).unwrap();
+
+        let entries = pinned.list().unwrap();
+        assert_eq!(ids(&entries), vec!["pin-2", "pin-1"]);
+        assert!(entries.iter().all(|e| is_pinned(&RofiEntry::id(e))));
+        assert!(!is_pinned("2"));
+        assert_eq!(pinned.decode("pin-1").unwrap(), b"a");
+        assert!(pinned.decode("1").is_err());
+        assert!(pinned.decode("pin-x").is_err());
+    }
+
+    #[test]
+    fn reorders_and_unpins() {
+        let dir = tempfile::tempdir().unwrap();
+        let pinned = new(dir.path());
+        for text in ["a", "b", "c"] {
+            pinned.store(text.as_bytes().to_vec()).unwrap();
+        }
+
+        pinned.shift("pin-1", true).unwrap();
+        assert_eq!(
+            ids(&pinned.list().unwrap()),
+            vec!["pin-3", "pin-1", "pin-2"]
+        );
+        pinned.delete("pin-3").unwrap();
+        let unpinned = pinned.delete_many(&["pin-2".into(), "pin-9".into()]);
+        assert_eq!(unpinned.unwrap(), vec!["pin-2"]);
+        assert_eq!(ids(&pinned.list().unwrap()), vec!["pin-1"]);
+    }
+
+    #[test]
+    fn finds_pinned_contents_in_the_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let pinned = new(dir.path().join("pinned"));
+        let history = store::new(dir.path().join("history"));
+        for text in ["pinned", "other", "pinned "] {
+            history.insert(text.as_bytes(), None).unwrap();
+        }
+        pinned.store(b"pinned".to_vec()).unwrap();
+
+        // "pinned " has the same preview, only its content tells it apart
+        let copies = pinned
+            .copies(&pinned.list().unwrap(), &history, &history.list().unwrap())
+            .unwrap();
+        assert_eq!(copies, HashSet::from(["1".to_string()]));
+        assert_eq!(pinned.read_digests().len(), 2);
+    }
+
+    /// History reading its entries from a store, counting the decoded ones.
+    struct Counting {
+        store: Store,
+        decoded: RefCell<Vec<String>>,
+    }
+
+    impl HistoryBackend for Counting {
+        fn list(&self) -> anyhow::Result<Vec<ClipHistEntry>> {
+            self.store.list()
+        }
+
+        fn decode(&self, id: &str) -> anyhow::Result<Vec<u8>> {
+            self.decoded.borrow_mut().push(id.into());
+            self.store.decode(id)
+        }
+
+        fn delete(&self, id: &str) -> anyhow::Result<()> {
+            self.store.delete(id)
+        }
+
+        fn store(&self, content: Vec<u8>) -> anyhow::Result<()> {
+            self.store.store(content)
+        }
+    }
+
+    #[test]
+    fn saves_the_digests_between_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = Counting {
+            store: store::new(dir.path().join("history")),
+            decoded: RefCell::new(Vec::new()),
+        };
+        for text in ["pinned", "other", "pinned "] {
+            history.store.insert(text.as_bytes(), None).unwrap();
+        }
+        new(dir.path().join("pinned"))
+            .store(b"pinned".to_vec())
+            .unwrap();
+
+        let copies = || {
+            let pinned = new(dir.path().join("pinned"));
+            let entries = history.list().unwrap();
+            pinned
+                .copies(&pinned.list().unwrap(), &history, &entries)
+                .unwrap()
+        };
+        assert_eq!(copies(), HashSet::from(["1".to_string()]));
+        assert_eq!(*history.decoded.borrow(), vec!["3", "1"]);
+
+        // a new run only decodes the new entries looking like a pinned one
+        history.decoded.borrow_mut().clear();
+        history.store.insert(b"pinned  ", None).unwrap();
+        history.store.remove(3).unwrap();
+        assert_eq!(copies(), HashSet::from(["1".to_string()]));
+        assert_eq!(*history.decoded.borrow(), vec!["4"]);
+        let digests = new(dir.path().join("pinned")).read_digests();
+        assert_eq!(digests.keys().collect::<Vec<_>>(), vec!["1", "4"]);
+    }
+}
@@ -0,0 +1,520 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context};
+use directories_next::BaseDirs;
+use log::{debug, trace, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    classify::{self, Category},
+    cliphist::{self, ClipHistEntry},
+    mime,
+};
+
+use super::{HistoryBackend, Meta};
+
+const INDEX_FILE: &str = "index.json";
+const LOCK_FILE: &str = "lock";
+const BLOBS_DIR: &str = "blobs";
+
+/// Built-in clipboard history, no external history manager needed.
+///
+/// The metadata of every entry lives in `index.json` (oldest first) and each content in
+/// `blobs/<id>`. Writers hold an exclusive lock on the `lock` file, readers a shared one, and the
+/// index is replaced atomically so a crash never leaves it half written.
+pub struct Store {
+    dir: PathBuf,
+}
+
+/// Metadata of a stored entry.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Record {
+    pub id: u64,
+    /// MIME type of binary content, `None` for text
+    pub mime: Option<String>,
+    /// Size of the content in bytes
+    pub size: u64,
+    /// SHA-256 of the content, used to deduplicate entries
+    pub hash: String,
+    /// Unix timestamp of the first time the content was stored
+    pub first_seen: u64,
+    /// Unix timestamp of the last time the content was stored
+    pub last_used: u64,
+    /// Single line preview of text content
+    #[serde(default)]
+    pub preview: String,
+    /// Pixel dimensions of images
+    #[serde(default)]
+    pub dimensions: Option<(u32, u32)>,
+    /// Kind of text content, classified using the full text instead of the preview
+    #[serde(default)]
+    pub category: Option<Category>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Index {
+    next_id: u64,
+    entries: Vec<Record>,
+}
+
+/// Create a store located in `dir`, its files being created on the first write.
+pub fn new(dir: impl Into<PathBuf>) -> Store {
+    let dir = dir.into();
+    trace!("Creating store on {dir:?}");
+    Store { dir }
+}
+
+/// Default location of the store, `$XDG_DATA_HOME/roto/history`.
+pub fn default_path() -> anyhow::Result<PathBuf> {
+    let dirs = BaseDirs::new().context("Error getting base directories")?;
+
+    Ok(dirs.data_dir().join("roto").join("history"))
+}
+
+impl Store {
+    /// All the records, newest first.
+    pub fn records(&self) -> anyhow::Result<Vec<Record>> {
+        let _lock = self.lock(false)?;
+        let mut entries = self.read_index()?.entries;
+        entries.reverse();
+
+        Ok(entries)
+    }
+
+    /// Store some content, an identical one being moved to the top instead of duplicated.
+    ///
+    /// `mime` is the type offered by the clipboard, if known, otherwise it's sniffed from the
+    /// content. Any text type is stored as text.
+    pub fn insert(&self, content: &[u8], mime: Option<&str>) -> anyhow::Result<Record> {
+        let _lock = self.lock(true)?;
+        let mut index = self.read_index()?;
+
+        let now = now();
+        let hash = format!("{:x}", Sha256::digest(content));
+        let id = index.next_id.max(1);
+        index.next_id = id + 1;
+
+        let previous = index
+            .entries
+            .iter()
+            .position(|r| r.hash == hash)
+            .map(|pos| index.entries.remove(pos));
+
+        let mime = match mime {
+            Some(mime) if mime::is_text(mime) => None,
+            Some(mime) => Some(mime),
+            None => mime::sniff(content),
+        };
+        let record = Record {
+            id,
+            mime: mime.map(String::from),
+            size: content.len() as u64,
+            first_seen: previous.as_ref().map(|r| r.first_seen).unwrap_or(now),
+            last_used: now,
+            preview: match mime {
+                Some(_) => String::new(),
+                None => cliphist::preview(&String::from_utf8_lossy(content)),
+            },
+            dimensions: mime::dimensions(content),
+            category: match mime {
+                Some(_) => None,
+                None => Some(classify::classify(&String::from_utf8_lossy(content))),
+            },
+            hash,
+        };
+
+        fs::create_dir_all(self.dir.join(BLOBS_DIR))
+            .context(format!("Error creating store {:?}", self.dir))?;
+        fs::write(self.blob(id), content).context(format!("Error writing entry {id}"))?;
+
+        index.entries.push(record.clone());
+        self.write_index(&index)?;
+
+        // only once the index no longer references it
+        if let Some(previous) = previous {
+            trace!("Entry {} moved to {id}", previous.id);
+            fs::remove_file(self.blob(previous.id))
+                .context(format!("Error removing entry {}", previous.id))?;
+        }
+
+        debug!("Stored entry {id} ({} bytes)", record.size);
+
+        Ok(record)
+    }
+
+    /// Move an entry one position towards the newest ones (`up`) or the oldest ones.
+    pub fn shift(&self, id: u64, up: bool) -> anyhow::Result<()> {
+        let _lock = self.lock(true)?;
+        let mut index = self.read_index()?;
+
+        let pos = index
+            .entries
+            .iter()
+            .position(|r| r.id == id)
+            .context(format!("Entry {id} not found in store"))?;
+        // the index is sorted oldest first
+        let other = match up {
+            true if pos + 1 < index.entries.len() => pos + 1,
+            false if pos > 0 => pos - 1,
+            _ => return Ok(()),
+        };
+        index.entries.swap(pos, other);
+
+        self.write_index(&index)
+    }
+
+    /// Remove the oldest entries beyond `max_entries`, returning how many were removed.
+    pub fn trim(&self, max_entries: usize) -> anyhow::Result<usize> {
+        let _lock = self.lock(true)?;
+        let mut index = self.read_index()?;
+
+        let excess = index.entries.len().saturating_sub(max_entries);
+        if excess == 0 {
+            return Ok(0);
+        }
+
+        let removed = index.entries.drain(..excess).collect::<Vec<_>>();
+        self.write_index(&index)?;
+        for record in &removed {
+            fs::remove_file(self.blob(record.id))
+                .context(format!("Error removing entry {}", record.id))?;
+        }
+
+        debug!("Removed {excess} entries beyond the limit of {max_entries}");
+
+        Ok(excess)
+    }
+
+    /// Remove an entry and its content.
+    pub fn remove(&self, id: u64) -> anyhow::Result<()> {
+        if self.remove_many(&[id])?.is_empty() {
+            bail!("Entry {id} not found in store");
+        }
+
+        Ok(())
+    }
+
+    /// Remove several entries updating the index once, returning the ids that were removed.
+    pub fn remove_many(&self, ids: &[u64]) -> anyhow::Result<Vec<u64>> {
+        let _lock = self.lock(true)?;
+        let mut index = self.read_index()?;
+
+        let (removed, kept) = index
+            .entries
+            .into_iter()
+            .partition::<Vec<_>, _>(|r| ids.contains(&r.id));
+        index.entries = kept;
+        if removed.is_empty() {
+            return Ok(vec![]);
+        }
+
+        self.write_index(&index)?;
+        for record in &removed {
+            if let Err(e) = fs::remove_file(self.blob(record.id)) {
+                warn!("Error removing content of entry {}: {e}", record.id);
+            }
+        }
+
+        debug!("Removed {} entries", removed.len());
+
+        Ok(removed.into_iter().map(|r| r.id).collect())
+    }
+
+    /// The content of an entry.
+    pub fn read(&self, id: u64) -> anyhow::Result<Vec<u8>> {
+        let _lock = self.lock(false)?;
+        fs::read(self.blob(id)).context(format!("Error reading entry {id}"))
+    }
+
+    fn blob(&self, id: u64) -> PathBuf {
+        self.dir.join(BLOBS_DIR).join(id.to_string())
+    }
+
+    fn read_index(&self) -> anyhow::Result<Index> {
+        let path = self.dir.join(INDEX_FILE);
+        if !path.exists() {
+            return Ok(Index::default());
+        }
+
+        let source = fs::read(&path).context(format!("Error reading {path:?}"))?;
+        serde_json::from_slice(&source).context(format!("Error parsing {path:?}"))
+    }
+
+    fn write_index(&self, index: &Index) -> anyhow::Result<()> {
+        let path = self.dir.join(INDEX_FILE);
+        let tmp = path.with_extension("json.tmp");
+
+        let json = serde_json::to_vec(index).context("Error serializing store index")?;
+        fs::write(&tmp, json).context(format!("Error writing {tmp:?}"))?;
+        fs::rename(&tmp, &path).context(format!("Error writing {path:?}"))
+    }
+
+    /// Lock the store until the returned file is dropped.
+    fn lock(&self, exclusive: bool) -> anyhow::Result<File> {
+        fs::create_dir_all(&self.dir).context(format!("Error creating store {:?}", self.dir))?;
+        let path = self.dir.join(LOCK_FILE);
+        let file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .context(format!("Error opening {path:?}"))?;
+
+        if exclusive {
+            file.lock()
+        } else {
+            file.lock_shared()
+        }
+        .context(format!("Error locking {path:?}"))?;
+
+        Ok(file)
+    }
+}
+
+impl From<Record> for ClipHistEntry {
+    fn from(record: Record) -> Self {
+        let id = record.id.to_string();
+        match record.mime {
+            None => ClipHistEntry::Text {
+                id,
+                category: record
+                    .category
+                    .unwrap_or_else(|| classify::classify(&record.preview)),
+                title: record.preview,
+            },
+            Some(mime) if mime::is_image(&mime) => ClipHistEntry::Image {
+                id,
+                content_type: mime::extension(&mime).into(),
+                size: record.size,
+                dimensions: record.dimensions,
+            },
+            Some(mime) => ClipHistEntry::Binary {
+                id,
+                mime,
+                size: record.size,
+            },
+        }
+    }
+}
+
+impl HistoryBackend for Store {
+    fn list(&self) -> anyhow::Result<Vec<ClipHistEntry>> {
+        trace!("Listing store entries");
+        let entries = self
+            .records()?
+            .into_iter()
+            .map(ClipHistEntry::from)
+            .collect::<Vec<_>>();
+
+        debug!("Found {} entries in store", entries.len());
+
+        Ok(entries)
+    }
+
+    fn decode(&self, id: &str) -> anyhow::Result<Vec<u8>> {
+        self.read(parse_id(id)?)
+    }
+
+    fn delete(&self, id: &str) -> anyhow::Result<()> {
+        self.remove(parse_id(id)?)
+    }
+
+    fn delete_many(&self, ids: &[String]) -> anyhow::Result<Vec<String>> {
+        let ids = ids
+            .iter()
+            .map(|id| parse_id(id))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(self
+            .remove_many(&ids)?
+            .into_iter()
+            .map(|id| id.to_string())
+            .collect())
+    }
+
+    fn store(&self, content: Vec<u8>) -> anyhow::Result<()> {
+        self.insert(&content, None).map(|_| ())
+    }
+
+    fn meta(&self) -> anyhow::Result<HashMap<String, Meta>> {
+        Ok(self
+            .records()?
+            .into_iter()
+            .map(|record| {
+                let meta = Meta {
+                    size: record.size,
+                    first_seen: record.first_seen,
+                };
+                (record.id.to_string(), meta)
+            })
+            .collect())
+    }
+}
+
+fn parse_id(id: &str) -> anyhow::Result<u64> {
+    id.parse().context(format!("Invalid store id: {id}"))
+}
+
+/// Current unix timestamp, in seconds.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, thread, time::Duration};
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::rofi::RofiEntry;
+
+    fn store() -> (TempDir, Store) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = new(dir.path().join("history"));
+        (dir, store)
+    }
+
+    fn ids(store: &Store) -> Vec<u64> {
+        store.records().unwrap().iter().map(|r| r.id).collect()
+    }
+
+    #[test]
+    fn reads_back_inserted_entries() {
+        let (_dir, store) = store();
+        assert!(store.list().unwrap().is_empty());
+
+        let png = crate::color::parse("#1e1e2e").unwrap().swatch();
+        store.insert(b"  hello\n  world ", None).unwrap();
+        store.insert(&png, None).unwrap();
+
+        let entries = store.list().unwrap();
+        assert!(matches!(
+            &entries[0],
+            ClipHistEntry::Image { id, content_type, dimensions: Some((32, 32)), .. }
+                if id == "2" && content_type == "png"
+        ));
+        assert!(matches!(
+            &entries[1],
+            ClipHistEntry::Text { id, title, .. } if id == "1" && title == "hello world"
+        ));
+        assert_eq!(store.decode("2").unwrap(), png);
+        assert_eq!(store.decode("1").unwrap(), b"  hello\n  world ");
+        assert_eq!(store.meta().unwrap()["2"].size, png.len() as u64);
+
+        // a new store reads the same files
+        let reopened = new(store.dir.clone());
+        assert_eq!(ids(&reopened), vec![2, 1]);
+    }
+
+    #[test]
+    fn moves_duplicates_to_the_top() {
+        let (_dir, store) = store();
+        let first = store.insert(b"a", None).unwrap();
+        store.insert(b"b", None).unwrap();
+        let again = store.insert(b"a", None).unwrap();
+
+        assert_eq!(ids(&store), vec![3, 2]);
+        assert_eq!(again.first_seen, first.first_seen);
+        assert_eq!(again.hash, first.hash);
+        assert!(!store.blob(1).exists());
+        assert_eq!(store.read(3).unwrap(), b"a");
+    }
+
+    #[test]
+    fn stores_text_mime_types_as_text() {
+        let (_dir, store) = store();
+        store.insert(b"<b>hi</b>", Some("text/html")).unwrap();
+        store
+            .insert(b"\x00\x01", Some("application/x-thing"))
+            .unwrap();
+
+        let entries = store.list().unwrap();
+        assert!(matches!(
+            &entries[0],
+            ClipHistEntry::Binary { mime, size: 2, .. } if mime == "application/x-thing"
+        ));
+        assert!(matches!(&entries[1], ClipHistEntry::Text { .. }));
+    }
+
+    #[test]
+    fn removes_entries() {
+        let (_dir, store) = store();
+        for text in ["a", "b", "c"] {
+            store.insert(text.as_bytes(), None).unwrap();
+        }
+
+        assert_eq!(store.remove_many(&[1, 3, 42]).unwrap(), vec![1, 3]);
+        assert_eq!(ids(&store), vec![2]);
+        assert!(!store.blob(1).exists() && !store.blob(3).exists());
+        assert!(store.remove(42).is_err());
+        assert_eq!(store.delete_many(&["2".into()]).unwrap(), vec!["2"]);
+        assert!(store.list().unwrap().is_empty());
+        assert!(store.delete("nope").is_err());
+    }
+
+    #[test]
+    fn trims_the_oldest_entries() {
+        let (_dir, store) = store();
+        for text in ["a", "b", "c", "d", "e"] {
+            store.insert(text.as_bytes(), None).unwrap();
+        }
+
+        assert_eq!(store.trim(10).unwrap(), 0);
+        assert_eq!(store.trim(2).unwrap(), 3);
+        assert_eq!(ids(&store), vec![5, 4]);
+        assert!((1..=3).all(|id| !store.blob(id).exists()));
+    }
+
+    #[test]
+    fn shifts_entries() {
+        let (_dir, store) = store();
+        for text in ["a", "b", "c"] {
+            store.insert(text.as_bytes(), None).unwrap();
+        }
+
+        store.shift(1, true).unwrap();
+        assert_eq!(ids(&store), vec![3, 1, 2]);
+        store.shift(3, true).unwrap();
+        assert_eq!(ids(&store), vec![3, 1, 2]);
+        store.shift(3, false).unwrap();
+        assert_eq!(ids(&store), vec![1, 3, 2]);
+        assert!(store.shift(42, true).is_err());
+    }
+
+    #[test]
+    fn waits_for_the_lock() {
+        let (_dir, store) = store();
+        store.insert(b"a", None).unwrap();
+
+        // flock locks belong to the open file, so this one conflicts like another process' would
+        let held = store.lock(true).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let writer = new(store.dir.clone());
+        let handle = thread::spawn(move || {
+            writer.insert(b"b", None).unwrap();
+            sender.send(()).unwrap();
+        });
+
+        assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+        drop(held);
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        handle.join().unwrap();
+        let ids = store
+            .list()
+            .unwrap()
+            .iter()
+            .map(RofiEntry::id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec!["2", "1"]);
+    }
+}
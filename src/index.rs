@@ -0,0 +1,314 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::PathBuf,
+};
+
+use anyhow::Context;
+use directories_next::BaseDirs;
+use log::{debug, trace, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{classify::Category, cliphist::ClipHistEntry, rofi::RofiEntry};
+
+/// Content kept per text, longer ones are cut off, in bytes.
+const MAX_TEXT_SIZE: usize = 64 * 1024;
+
+/// Full content of the texts the history's previews cut off, so they can be searched.
+///
+/// Texts are decoded once and saved in a file, only the new entries are decoded on the following
+/// runs and the ones gone from the history are forgotten.
+pub struct TextIndex {
+    path: PathBuf,
+    texts: BTreeMap<String, Indexed>,
+}
+
+/// An indexed text, along with the preview it was indexed for, so a reused id isn't mistaken for
+/// the same entry.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Indexed {
+    preview: String,
+    text: String,
+}
+
+/// Load the index saved in `path`, empty if there's none yet.
+pub fn new(path: impl Into<PathBuf>) -> anyhow::Result<TextIndex> {
+    let path = path.into();
+    trace!("Loading text index from {path:?}");
+
+    let texts = match path.exists() {
+        true => {
+            let source = fs::read(&path).context(format!("Error reading {path:?}"))?;
+            // a corrupted index is rebuilt
+            serde_json::from_slice(&source).unwrap_or_else(|e| {
+                warn!("Error parsing {path:?}, rebuilding it: {e}");
+                BTreeMap::new()
+            })
+        }
+        false => BTreeMap::new(),
+    };
+
+    Ok(TextIndex { path, texts })
+}
+
+/// Default location of the index, `$XDG_CACHE_HOME/rofi-cliphist/index.json`.
+pub fn default_path() -> anyhow::Result<PathBuf> {
+    let dirs = BaseDirs::new().context("Error getting base directories")?;
+
+    Ok(dirs.cache_dir().join("rofi-cliphist").join("index.json"))
+}
+
+impl TextIndex {
+    /// Index the cut off texts among the entries using `decode`, forgetting the ones no longer
+    /// listed. Returns the number of texts added.
+    ///
+    /// Secrets are never indexed: neither the masked entries nor the texts `is_secret` detects.
+    pub fn update(
+        &mut self,
+        entries: &[ClipHistEntry],
+        decode: impl Fn(&str) -> anyhow::Result<Vec<u8>>,
+        is_secret: impl Fn(&str) -> bool,
+    ) -> anyhow::Result<usize> {
+        let saved = self.texts.clone();
+
+        let mut texts = BTreeMap::new();
+        for entry in entries {
+            let ClipHistEntry::Text {
+                id,
+                title,
+                category,
+            } = entry
+            else {
+                continue;
+            };
+            if !title.ends_with('…') || *category == Category::Secret {
+                continue;
+            }
+
+            let indexed = match self.texts.remove(id) {
+                Some(indexed) if indexed.preview == *title => indexed,
+                _ => {
+                    let content = match decode(id) {
+                        Ok(content) => content,
+                        Err(e) => {
+                            warn!("Error indexing history entry {id}: {e:?}");
+                            continue;
+                        }
+                    };
+                    let text = String::from_utf8_lossy(&content);
+                    if is_secret(&text) {
+                        continue;
+                    }
+                    Indexed {
+                        preview: title.clone(),
+                        text: truncate(&searchable(&text)),
+                    }
+                }
+            };
+            texts.insert(id.clone(), indexed);
+        }
+        let added = texts.keys().filter(|id| !saved.contains_key(*id)).count();
+        debug!("Indexed {added} new texts, {} in total", texts.len());
+
+        self.texts = texts;
+        if self.texts != saved {
+            self.write()?;
+        }
+
+        Ok(added)
+    }
+
+    /// Forget the texts no longer listed among the entries, returning how many were.
+    pub fn retain(&mut self, entries: &[ClipHistEntry]) -> anyhow::Result<usize> {
+        let ids = entries.iter().map(RofiEntry::id).collect::<HashSet<_>>();
+        let before = self.texts.len();
+        self.texts.retain(|id, _| ids.contains(id));
+
+        let removed = before - self.texts.len();
+        if removed > 0 {
+            self.write()?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Number of indexed texts.
+    pub fn len(&self) -> usize {
+        self.texts.len()
+    }
+
+    /// Whether no text is indexed.
+    pub fn is_empty(&self) -> bool {
+        self.texts.is_empty()
+    }
+
+    /// Full content of a text, if it was indexed.
+    pub fn get(&self, id: &str) -> Option<&str> {
+        self.texts.get(id).map(|indexed| indexed.text.as_str())
+    }
+
+    fn write(&self) -> anyhow::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).context(format!("Error creating {dir:?}"))?;
+        }
+        let json = serde_json::to_vec(&self.texts).context("Error serializing text index")?;
+
+        fs::write(&self.path, json).context(format!("Error writing {:?}", self.path))
+    }
+}
+
+/// Text on a single line, without the characters rofi reads as row options separators.
+fn searchable(text: &str) -> String {
+    text.replace(['\0', '\x1f'], " ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Text cut off at [`MAX_TEXT_SIZE`], on a character boundary.
+fn truncate(text: &str) -> String {
+    let mut end = text.len().min(MAX_TEXT_SIZE);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    text[..end].into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    fn text(id: &str, title: &str, category: Category) -> ClipHistEntry {
+        ClipHistEntry::Text {
+            id: id.into(),
+            title: title.into(),
+            category,
+        }
+    }
+
+    fn cut(id: &str, title: &str) -> ClipHistEntry {
+        text(id, title, Category::Text)
+    }
+
+    fn not_secret(_: &str) -> bool {
+        false
+    }
+
+    #[test]
+    fn only_decodes_the_new_texts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.json");
+        let decoded = RefCell::new(Vec::new());
+        let decode = |id: &str| {
+            decoded.borrow_mut().push(id.to_string());
+            Ok(format!("full text {id}").into_bytes())
+        };
+
+        let mut index = new(&path).unwrap();
+        let entries = [cut("2", "full…"), cut("1", "full…"), cut("0", "short")];
+        assert_eq!(index.update(&entries, decode, not_secret).unwrap(), 2);
+        assert_eq!(*decoded.borrow(), vec!["2", "1"]);
+        assert_eq!(index.get("2"), Some("full text 2"));
+        assert_eq!(index.get("0"), None);
+
+        // saved, a new run only decodes the new entry and forgets the deleted one
+        decoded.borrow_mut().clear();
+        let mut index = new(&path).unwrap();
+        let entries = [cut("3", "full…"), cut("2", "full…")];
+        assert_eq!(index.update(&entries, decode, not_secret).unwrap(), 1);
+        assert_eq!(*decoded.borrow(), vec!["3"]);
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.get("1"), None);
+    }
+
+    #[test]
+    fn decodes_reused_ids_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut index = new(dir.path().join("index.json")).unwrap();
+
+        index
+            .update(
+                &[cut("1", "old…")],
+                |_| Ok(b"old text".to_vec()),
+                not_secret,
+            )
+            .unwrap();
+        index
+            .update(
+                &[cut("1", "new…")],
+                |_| Ok(b"new text".to_vec()),
+                not_secret,
+            )
+            .unwrap();
+        assert_eq!(index.get("1"), Some("new text"));
+    }
+
+    #[test]
+    fn never_indexes_secrets() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut index = new(dir.path().join("index.json")).unwrap();
+
+        let entries = [
+            text("3", "••••• (secret)…", Category::Secret),
+            cut("2", "token…"),
+            cut("1", "fine…"),
+            cut("0", "broken…"),
+        ];
+        let decode = |id: &str| match id {
+            "0" => anyhow::bail!("gone"),
+            _ => Ok(format!("text {id}").into_bytes()),
+        };
+        let added = index
+            .update(&entries, decode, |text| text == "text 2")
+            .unwrap();
+
+        assert_eq!(added, 1);
+        assert_eq!(index.get("1"), Some("text 1"));
+        assert_eq!(index.len(), 1);
+    }
+
+    #[test]
+    fn forgets_the_deleted_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.json");
+        let mut index = new(&path).unwrap();
+        let decode = |id: &str| Ok(id.as_bytes().to_vec());
+        index
+            .update(&[cut("2", "a…"), cut("1", "b…")], decode, not_secret)
+            .unwrap();
+
+        assert_eq!(index.retain(&[cut("2", "a…")]).unwrap(), 1);
+        assert_eq!(index.retain(&[cut("2", "a…")]).unwrap(), 0);
+        assert_eq!(new(&path).unwrap().len(), 1);
+        assert_eq!(index.get("1"), None);
+    }
+
+    #[test]
+    fn rebuilds_a_corrupted_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.json");
+        fs::write(&path, "{").unwrap();
+
+        assert!(new(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn keeps_texts_searchable_by_rofi() {
+        assert_eq!(searchable("a\nb\0c\x1fd\t e  "), "a b c d e");
+        assert_eq!(searchable("\n\n"), "");
+    }
+
+    #[test]
+    fn truncates_on_char_boundaries() {
+        assert_eq!(truncate("short"), "short");
+
+        let long = "x".repeat(MAX_TEXT_SIZE + 10);
+        assert_eq!(truncate(&long).len(), MAX_TEXT_SIZE);
+        // a 2 bytes character across the limit is left out
+        let long = format!("{}ñ", "x".repeat(MAX_TEXT_SIZE - 1));
+        assert_eq!(truncate(&long).len(), MAX_TEXT_SIZE - 1);
+    }
+}
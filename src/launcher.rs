@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cache,
+    rofi::{Rofi, RofiEntry, RofiOptions, RofiResult},
+};
+
+pub mod dmenu;
+pub mod fzf;
+
+/// A menu program able to show entries and report the user's choice.
+///
+/// `A` is the action bound to the custom keyboard shortcuts of the [`RofiOptions`]. Launchers
+/// without custom shortcuts are expected to offer the actions some other way.
+pub trait Launcher<A> {
+    /// Show the entries and wait for the user's choice.
+    fn run(
+        &mut self,
+        entries: &[&dyn RofiEntry],
+        options: &RofiOptions<A>,
+        cache: &cache::SimpleCache,
+    ) -> anyhow::Result<RofiResult<A>>;
+
+    /// Opaque state saved by a previous run of the program, if the launcher keeps any.
+    fn data(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// Supported launchers
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Rofi,
+    Fuzzel,
+    Wofi,
+    Tofi,
+    Bemenu,
+    Fzf,
+}
+
+impl Kind {
+    /// Default executable name.
+    pub fn bin(&self) -> &'static str {
+        match self {
+            Kind::Rofi => "rofi",
+            Kind::Fuzzel => "fuzzel",
+            Kind::Wofi => "wofi",
+            Kind::Tofi => "tofi",
+            Kind::Bemenu => "bemenu",
+            Kind::Fzf => "fzf",
+        }
+    }
+}
+
+impl<A: Copy> Launcher<A> for Rofi {
+    fn run(
+        &mut self,
+        entries: &[&dyn RofiEntry],
+        options: &RofiOptions<A>,
+        cache: &cache::SimpleCache,
+    ) -> anyhow::Result<RofiResult<A>> {
+        Rofi::run(self, entries, options, cache)
+    }
+
+    fn data(&self) -> Option<&str> {
+        Rofi::data(self)
+    }
+}
@@ -0,0 +1,351 @@
+use std::{
+    io::{Read, Write},
+    process::{Command, Stdio},
+};
+
+use anyhow::{bail, Context};
+use log::{debug, trace};
+
+use crate::{
+    cache,
+    rofi::{RofiEntry, RofiOptions, RofiResult},
+};
+
+use super::{Kind, Launcher};
+
+/// Label of the secondary menu entry that simply accepts the selected entry.
+const SELECT_LABEL: &str = "Select";
+
+/// Launchers speaking the dmenu protocol: entries on stdin, selected text on stdout.
+///
+/// None of them support rofi's custom keyboard shortcuts, so whenever the options define some, a
+/// secondary menu listing the actions is shown after selecting an entry.
+pub struct Dmenu {
+    bin: String,
+    kind: Kind,
+}
+
+/// Create a dmenu-like launcher of the given kind, failing for the launchers that aren't dmenu-like
+/// (rofi and fzf).
+pub fn new(kind: Kind, bin: impl Into<String>) -> anyhow::Result<Dmenu> {
+    trace!("Creating {kind:?} launcher");
+    if let Kind::Rofi | Kind::Fzf = kind {
+        bail!("{kind:?} has its own launcher, not a dmenu one");
+    }
+
+    Ok(Dmenu {
+        bin: bin.into(),
+        kind,
+    })
+}
+
+impl<A: Copy> Launcher<A> for Dmenu {
+    fn run(
+        &mut self,
+        entries: &[&dyn RofiEntry],
+        options: &RofiOptions<A>,
+        cache: &cache::SimpleCache,
+    ) -> anyhow::Result<RofiResult<A>> {
+        if entries.is_empty() && options.no_custom {
+            debug!("No entries to show");
+            return Ok(RofiResult::Empty);
+        }
+
+        let labels = labels(entries);
+        let icons = entries
+            .iter()
+            .map(|e| {
+                e.icon().map(|icon| {
+                    let cached = cache.path(&icon);
+                    if cached.exists() {
+                        cached.to_string_lossy().to_string()
+                    } else {
+                        icon
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let Some(selected) = self.show(&labels, &icons, options)? else {
+            return Ok(RofiResult::Cancel);
+        };
+
+        let Some(id) = self.position(&labels, &selected) else {
+            return Ok(if options.no_custom {
+                RofiResult::Cancel
+            } else {
+                RofiResult::Custom(selected)
+            });
+        };
+
+        if options.custom_kbs.is_empty() {
+            return Ok(RofiResult::Selection { id });
+        }
+
+        // no custom shortcuts, ask for the action in a secondary menu instead
+        let actions = std::iter::once(SELECT_LABEL.to_string())
+            .chain(options.custom_kbs.iter().map(|kb| kb.description().into()))
+            .collect::<Vec<_>>();
+        let no_icons = vec![None; actions.len()];
+        let mut action_options = RofiOptions::<A>::default();
+        action_options.prompt = Some(labels[id].clone());
+
+        let result = match self.show(&actions, &no_icons, &action_options)? {
+            None => RofiResult::Cancel,
+            Some(action) => match self.position(&actions, &action) {
+                Some(0) => RofiResult::Selection { id },
+                Some(idx) => RofiResult::Action {
+                    action: *options.custom_kbs[idx - 1].action(),
+                    id,
+                },
+                None => RofiResult::Cancel,
+            },
+        };
+
+        Ok(result)
+    }
+}
+
+impl Dmenu {
+    /// Spawn the launcher with the given rows, returning the selected (or typed) text.
+    fn show<A>(
+        &self,
+        labels: &[String],
+        icons: &[Option<String>],
+        options: &RofiOptions<A>,
+    ) -> anyhow::Result<Option<String>> {
+        let args = self.args(options);
+        debug!("Executing {:?} with args: {:?}", &self.bin, &args);
+
+        let mut process = Command::new(&self.bin)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .args(&args)
+            .spawn()
+            .context(format!("Error executing {}", self.bin))?;
+
+        if let Some(mut writer) = process.stdin.take() {
+            for (label, icon) in labels.iter().zip(icons) {
+                let mut row = label.clone();
+                // fuzzel understands rofi's icon row option, the rest would display it as text
+                if let (Kind::Fuzzel, Some(icon)) = (self.kind, icon) {
+                    row.push_str(&format!("\0icon\x1f{icon}"));
+                }
+                row.push('\n');
+                writer
+                    .write_all(row.as_bytes())
+                    .context(format!("writing entries to {} through stdin", self.bin))?;
+            }
+        }
+
+        let status = process
+            .wait()
+            .context(format!("waiting {}'s execution", self.bin))?;
+
+        let mut buffer = String::new();
+        if let Some(mut reader) = process.stdout.take() {
+            reader
+                .read_to_string(&mut buffer)
+                .context(format!("reading {}'s output", self.bin))?;
+        }
+        if buffer.ends_with('\n') {
+            buffer.pop();
+        }
+
+        if !status.success() || buffer.is_empty() {
+            trace!("{} exited with {status}", self.bin);
+            return Ok(None);
+        }
+
+        Ok(Some(buffer))
+    }
+
+    /// Position of the selected row, `None` if the text was typed by the user.
+    fn position(&self, labels: &[String], selected: &str) -> Option<usize> {
+        match self.kind {
+            Kind::Fuzzel => selected
+                .parse::<usize>()
+                .ok()
+                .filter(|idx| *idx < labels.len()),
+            _ => labels.iter().position(|l| *l == selected),
+        }
+    }
+
+    /// Translate the options into the launcher's command line flags.
+    fn args<A>(&self, options: &RofiOptions<A>) -> Vec<String> {
+        let prompt = options.prompt.as_deref();
+        let mut args: Vec<String> = Vec::new();
+        match self.kind {
+            Kind::Fuzzel => {
+                args.extend(["--dmenu".into(), "--index".into()]);
+                if let Some(prompt) = prompt {
+                    args.extend(["--prompt".into(), format!("{prompt}> ")]);
+                }
+                if options.no_custom {
+                    args.push("--only-match".into());
+                }
+            }
+            Kind::Wofi => {
+                args.push("--dmenu".into());
+                if options.case_insensitive {
+                    args.push("--insensitive".into());
+                }
+                if let Some(prompt) = prompt {
+                    args.extend(["--prompt".into(), prompt.into()]);
+                }
+            }
+            Kind::Tofi => {
+                if let Some(prompt) = prompt {
+                    args.extend(["--prompt-text".into(), format!("{prompt}: ")]);
+                }
+                if !options.no_custom {
+                    args.push("--require-match=false".into());
+                }
+            }
+            Kind::Bemenu => {
+                args.extend(["--list".into(), "15".into()]);
+                if options.case_insensitive {
+                    args.push("--ignorecase".into());
+                }
+                if let Some(prompt) = prompt {
+                    args.extend(["--prompt".into(), prompt.into()]);
+                }
+            }
+            // rejected by `new`
+            Kind::Rofi | Kind::Fzf => {}
+        }
+        args
+    }
+}
+
+/// Rows of the entries, in order.
+///
+/// The selection is reported by its text (except for fuzzel, asked for its index), so identical
+/// rows (e.g. two images of the same size) are told apart using the entry's id.
+fn labels(entries: &[&dyn RofiEntry]) -> Vec<String> {
+    let mut labels: Vec<String> = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let label = row_label(&entry.label());
+        if labels.contains(&label) {
+            labels.push(format!("{label} [{}]", entry.id()));
+        } else {
+            labels.push(label);
+        }
+    }
+    labels
+}
+
+/// Label as written on its own row, the selection being reported with this exact text.
+fn row_label(label: &str) -> String {
+    label.replace(['\n', '\r', '\0'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Entry {
+        id: &'static str,
+        label: &'static str,
+    }
+
+    impl RofiEntry for Entry {
+        fn id(&self) -> String {
+            self.id.into()
+        }
+        fn label(&self) -> String {
+            self.label.into()
+        }
+        fn icon(&self) -> Option<String> {
+            None
+        }
+        fn meta(&self) -> Option<String> {
+            None
+        }
+    }
+
+    fn options(prompt: Option<&str>, no_custom: bool) -> RofiOptions<()> {
+        let mut options = RofiOptions::default();
+        options.prompt = prompt.map(Into::into);
+        options.no_custom = no_custom;
+        options
+    }
+
+    fn args(kind: Kind, options: &RofiOptions<()>) -> Vec<String> {
+        new(kind, "launcher").unwrap().args(options)
+    }
+
+    #[test]
+    fn rejects_launchers_of_their_own() {
+        assert!(new(Kind::Rofi, "rofi").is_err());
+        assert!(new(Kind::Fzf, "fzf").is_err());
+    }
+
+    #[test]
+    fn builds_the_arguments_of_each_launcher() {
+        let prompted = options(Some("p"), true);
+        let custom = options(None, false);
+
+        assert_eq!(
+            args(Kind::Fuzzel, &prompted),
+            ["--dmenu", "--index", "--prompt", "p> ", "--only-match"]
+        );
+        assert_eq!(args(Kind::Fuzzel, &custom), ["--dmenu", "--index"]);
+        assert_eq!(
+            args(Kind::Wofi, &prompted),
+            ["--dmenu", "--insensitive", "--prompt", "p"]
+        );
+        assert_eq!(args(Kind::Tofi, &prompted), ["--prompt-text", "p: "]);
+        assert_eq!(args(Kind::Tofi, &custom), ["--require-match=false"]);
+        assert_eq!(
+            args(Kind::Bemenu, &prompted),
+            ["--list", "15", "--ignorecase", "--prompt", "p"]
+        );
+
+        let mut sensitive = options(None, true);
+        sensitive.case_insensitive = false;
+        assert_eq!(args(Kind::Wofi, &sensitive), ["--dmenu"]);
+        assert_eq!(args(Kind::Bemenu, &sensitive), ["--list", "15"]);
+    }
+
+    #[test]
+    fn tells_identical_rows_apart() {
+        let entries = [
+            Entry {
+                id: "3",
+                label: "image 10x10",
+            },
+            Entry {
+                id: "2",
+                label: "multi\nline",
+            },
+            Entry {
+                id: "1",
+                label: "image 10x10",
+            },
+        ];
+        let entries = entries
+            .iter()
+            .map(|e| e as &dyn RofiEntry)
+            .collect::<Vec<_>>();
+        let labels = labels(&entries);
+        assert_eq!(labels, ["image 10x10", "multi line", "image 10x10 [1]"]);
+
+        let wofi = new(Kind::Wofi, "wofi").unwrap();
+        assert_eq!(wofi.position(&labels, "image 10x10"), Some(0));
+        assert_eq!(wofi.position(&labels, "image 10x10 [1]"), Some(2));
+        assert_eq!(wofi.position(&labels, "typed"), None);
+    }
+
+    #[test]
+    fn resolves_fuzzel_indexes() {
+        let labels = ["a".to_string(), "a [1]".to_string()];
+        let fuzzel = new(Kind::Fuzzel, "fuzzel").unwrap();
+
+        assert_eq!(fuzzel.position(&labels, "1"), Some(1));
+        assert_eq!(fuzzel.position(&labels, "2"), None);
+        assert_eq!(fuzzel.position(&labels, "a"), None);
+        assert_eq!(fuzzel.position(&labels, "-1"), None);
+    }
+}
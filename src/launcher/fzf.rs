@@ -0,0 +1,268 @@
+use std::{
+    io::{Read, Write},
+    process::{Command, Stdio},
+};
+
+use anyhow::{bail, Context};
+use log::{debug, trace};
+
+use crate::{
+    cache,
+    rofi::{KbCustom, RofiEntry, RofiOptions, RofiResult},
+};
+
+use super::Launcher;
+
+/// Exit code fzf uses when it's interrupted (`Esc`, `Ctrl+c`).
+const INTERRUPTED: i32 = 130;
+/// Exit code fzf uses on errors, e.g. invalid flags.
+const ERROR: i32 = 2;
+/// ANSI escapes dimming the meta column.
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// Terminal launcher driving `fzf`, with a preview pane showing the highlighted entry.
+///
+/// Rows are sent as `index\tid\tfile\tlabel\tmeta`, only the label and the meta being displayed,
+/// the latter dimmed: fzf can only search what it displays, and the meta makes the type and size of
+/// the entries searchable (e.g. `image/png` or `url:`) like with rofi. The preview commands
+/// are shell templates where `{id}` is replaced by the entry's id and `{file}` by the path of its
+/// cached image (empty for texts). Custom shortcuts are mapped to fzf's `--expect` keys.
+pub struct Fzf {
+    bin: String,
+    text_preview: String,
+    image_preview: String,
+}
+
+/// Create a fzf launcher using the given preview command templates.
+pub fn new(
+    bin: impl Into<String>,
+    text_preview: impl Into<String>,
+    image_preview: impl Into<String>,
+) -> Fzf {
+    trace!("Creating fzf launcher");
+    Fzf {
+        bin: bin.into(),
+        text_preview: text_preview.into(),
+        image_preview: image_preview.into(),
+    }
+}
+
+impl<A: Copy> Launcher<A> for Fzf {
+    fn run(
+        &mut self,
+        entries: &[&dyn RofiEntry],
+        options: &RofiOptions<A>,
+        cache: &cache::SimpleCache,
+    ) -> anyhow::Result<RofiResult<A>> {
+        let args = self.args(options);
+        debug!("Executing {:?} with args: {:?}", &self.bin, &args);
+
+        let mut process = Command::new(&self.bin)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .args(&args)
+            .spawn()
+            .context("Error executing fzf")?;
+
+        if let Some(mut writer) = process.stdin.take() {
+            for (idx, entry) in entries.iter().enumerate() {
+                let file = entry
+                    .icon()
+                    .map(|icon| cache.path(&icon))
+                    .filter(|path| path.exists())
+                    .map(|path| path.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                let label = entry.display().unwrap_or_else(|| entry.label());
+                let meta = entry
+                    .meta()
+                    .map(|meta| format!("{DIM}{}{RESET}", field(&meta)))
+                    .unwrap_or_default();
+                let row = format!("{idx}\t{}\t{file}\t{}\t{meta}\n", entry.id(), field(&label));
+                writer
+                    .write_all(row.as_bytes())
+                    .context("writing entries to fzf through stdin")?;
+            }
+        }
+
+        let status = process.wait().context("waiting fzf's execution")?;
+
+        let mut buffer = String::new();
+        if let Some(mut reader) = process.stdout.take() {
+            reader
+                .read_to_string(&mut buffer)
+                .context("reading fzf's output")?;
+        }
+
+        match status.code() {
+            Some(INTERRUPTED) => {
+                trace!("fzf interrupted");
+                return Ok(RofiResult::Cancel);
+            }
+            Some(ERROR) => bail!("fzf exited with {status}"),
+            _ => {}
+        }
+
+        // --print-query and --expect: the query, the pressed key (only when expecting any) and
+        // then the selected rows
+        let mut lines = buffer.lines();
+        let query = lines.next().unwrap_or_default();
+        let key = match options.custom_kbs.is_empty() {
+            true => "",
+            false => lines.next().unwrap_or_default(),
+        };
+        let ids = lines
+            .map(|line| {
+                let (idx, _) = line.split_once('\t').unwrap_or((line, ""));
+                idx.parse::<usize>().context("parsing usize")
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let action = options
+            .custom_kbs
+            .iter()
+            .find(|kb| fzf_key(kb.shortcut()) == key)
+            .map(|kb| *kb.action());
+
+        let result = match (key.is_empty(), action, ids.as_slice()) {
+            (true, _, []) if !query.is_empty() && !options.no_custom => {
+                RofiResult::Custom(query.into())
+            }
+            (_, _, []) => RofiResult::Cancel,
+            (true, _, [id]) => RofiResult::Selection { id: *id },
+            (true, _, ids) => RofiResult::MultiSelection { ids: ids.to_vec() },
+            (false, Some(action), [id]) => RofiResult::Action { action, id: *id },
+            (false, Some(action), ids) => RofiResult::MultiAction {
+                action,
+                ids: ids.to_vec(),
+            },
+            (false, None, _) => {
+                trace!("Ignoring unbound key: {key}");
+                RofiResult::Cancel
+            }
+        };
+
+        Ok(result)
+    }
+}
+
+impl Fzf {
+    /// Translate the options into fzf's command line flags.
+    fn args<A>(&self, options: &RofiOptions<A>) -> Vec<String> {
+        let mut args: Vec<String> = vec![
+            "--delimiter=\t".into(),
+            "--with-nth=4..".into(),
+            "--ansi".into(),
+            "--print-query".into(),
+            "--preview-window=right:50%:wrap".into(),
+            format!("--bind=load:pos({})", options.selected_row + 1),
+            format!("--preview={}", self.preview()),
+        ];
+        if options.case_insensitive {
+            args.push("-i".into());
+        }
+        if options.multi_select {
+            args.push("--multi".into());
+        }
+        if let Some(prompt) = &options.prompt {
+            args.push(format!("--prompt={prompt}> "));
+        }
+        // fzf rejects an empty key list
+        let keys = options
+            .custom_kbs
+            .iter()
+            .map(|kb| fzf_key(kb.shortcut()))
+            .collect::<Vec<_>>();
+        if !keys.is_empty() {
+            args.push(format!("--expect={}", keys.join(",")));
+        }
+        if let Some(header) = header(&options.custom_kbs) {
+            args.push(format!("--header={header}"));
+        }
+        args
+    }
+
+    /// Shell command fzf runs to preview the highlighted row.
+    fn preview(&self) -> String {
+        let expand = |template: &str| template.replace("{id}", "{2}").replace("{file}", "{3}");
+        format!(
+            "if [ -n {{3}} ]; then {}; else {}; fi",
+            expand(&self.image_preview),
+            expand(&self.text_preview)
+        )
+    }
+}
+
+/// Text written as a single field of a row, without the entry's own ANSI escapes.
+fn field(text: &str) -> String {
+    text.replace(['\n', '\t'], " ").replace('\x1b', "")
+}
+
+/// Help line listing the custom shortcuts, if any.
+fn header<A>(custom_kbs: &[KbCustom<A>]) -> Option<String> {
+    if custom_kbs.is_empty() {
+        return None;
+    }
+
+    let help = custom_kbs
+        .iter()
+        .map(|kb| format!("{}: {}", fzf_key(kb.shortcut()), kb.description()))
+        .collect::<Vec<_>>();
+
+    Some(help.join(" | "))
+}
+
+/// Translate a rofi shortcut (e.g. `Control+Alt+d`) into fzf's key names (`ctrl-alt-d`).
+///
+/// fzf has no `shift` modifier for letters, they're written uppercase instead (`Shift+d` is `D`).
+fn fzf_key(shortcut: &str) -> String {
+    let mut parts = shortcut
+        .split('+')
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>();
+    let key = parts.pop().unwrap_or_default();
+    let shift = parts.iter().any(|part| part == "shift");
+
+    let mut names = parts
+        .iter()
+        .filter(|part| *part != "shift")
+        .map(|part| match part.as_str() {
+            "control" | "ctrl" => "ctrl".to_string(),
+            "alt" | "mod1" => "alt".to_string(),
+            modifier => modifier.to_string(),
+        })
+        .collect::<Vec<_>>();
+    let mut chars = key.chars();
+    match (shift, chars.next(), chars.next()) {
+        (true, Some(letter), None) => names.push(letter.to_uppercase().to_string()),
+        (true, _, _) => names.extend(["shift".to_string(), key]),
+        (false, _, _) => names.push(key),
+    }
+
+    names.join("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translates_shortcuts_to_fzf_keys() {
+        assert_eq!(fzf_key("Alt+d"), "alt-d");
+        assert_eq!(fzf_key("Control+Alt+d"), "ctrl-alt-d");
+        assert_eq!(fzf_key("ctrl+x"), "ctrl-x");
+        assert_eq!(fzf_key("Mod1+Return"), "alt-return");
+        assert_eq!(fzf_key("Alt+Up"), "alt-up");
+        assert_eq!(fzf_key("F5"), "f5");
+    }
+
+    #[test]
+    fn writes_shifted_letters_uppercase() {
+        assert_eq!(fzf_key("Shift+d"), "D");
+        assert_eq!(fzf_key("Alt+Shift+d"), "alt-D");
+        assert_eq!(fzf_key("Shift+Alt+D"), "alt-D");
+        assert_eq!(fzf_key("Shift+Up"), "shift-up");
+        assert_eq!(fzf_key("Alt+Shift+Left"), "alt-shift-left");
+    }
+}
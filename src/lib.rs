@@ -1,5 +1,17 @@
+pub mod action;
 pub mod cache;
+pub mod classify;
 pub mod clipboard;
 pub mod cliphist;
+pub mod color;
 pub mod config;
+pub mod history;
+pub mod index;
+pub mod launcher;
+pub mod mime;
+pub mod open;
+pub mod retention;
 pub mod rofi;
+pub mod secret;
+pub mod snippet;
+pub mod transform;
@@ -0,0 +1,140 @@
+/// Known MIME types with their magic numbers (at the given offset) and usual file extension.
+const SIGNATURES: &[(&str, usize, &[u8], &str)] = &[
+    ("image/png", 0, b"\x89PNG\r\n\x1a\n", "png"),
+    ("image/jpeg", 0, b"\xff\xd8\xff", "jpeg"),
+    ("image/gif", 0, b"GIF8", "gif"),
+    ("image/bmp", 0, b"BM", "bmp"),
+    ("image/webp", 8, b"WEBP", "webp"),
+    ("image/tiff", 0, b"II*\0", "tiff"),
+    ("image/tiff", 0, b"MM\0*", "tiff"),
+    ("image/avif", 4, b"ftypavif", "avif"),
+    ("application/pdf", 0, b"%PDF-", "pdf"),
+    ("application/zip", 0, b"PK\x03\x04", "zip"),
+    ("application/gzip", 0, b"\x1f\x8b", "gz"),
+    ("audio/mpeg", 0, b"ID3", "mp3"),
+    ("audio/ogg", 0, b"OggS", "ogg"),
+    ("audio/flac", 0, b"fLaC", "flac"),
+    ("audio/wav", 8, b"WAVE", "wav"),
+    ("video/mp4", 4, b"ftyp", "mp4"),
+];
+
+/// Extensions without a magic number, or commonly used as an alias.
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("image/jpeg", "jpg"),
+    ("image/svg+xml", "svg"),
+    ("image/tiff", "tif"),
+];
+
+/// MIME type of binary data that isn't recognized.
+pub const OCTET_STREAM: &str = "application/octet-stream";
+
+/// Detect the MIME type of some clipboard content, `None` for plain text.
+///
+/// Binary data is recognized by its magic number, falling back to [`OCTET_STREAM`]. Text is only
+/// checked for an SVG root element, so text starting like a magic number (e.g. `BM`) stays text.
+pub fn sniff(data: &[u8]) -> Option<&'static str> {
+    let text = std::str::from_utf8(data)
+        .ok()
+        .filter(|text| !text.contains('\0'));
+    let Some(text) = text else {
+        return Some(magic(data).unwrap_or(OCTET_STREAM));
+    };
+
+    let head = text.trim_start();
+    if head.starts_with("<svg") || (head.starts_with("<?xml") && head.contains("<svg")) {
+        return Some("image/svg+xml");
+    }
+
+    None
+}
+
+/// MIME type matching the magic number the data starts with, if any.
+pub fn magic(data: &[u8]) -> Option<&'static str> {
+    SIGNATURES
+        .iter()
+        .find(|(_, offset, magic, _)| {
+            data.get(*offset..*offset + magic.len())
+                .is_some_and(|prefix| prefix == *magic)
+        })
+        .map(|(mime, ..)| *mime)
+}
+
+/// Pixel dimensions (width, height) of a png, jpeg, gif, bmp or webp image, read from its header.
+pub fn dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let be16 = |at: usize| Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?) as u32);
+    let le16 = |at: usize| Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?) as u32);
+    let be32 = |at: usize| Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?));
+    let le32 = |at: usize| Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?));
+    let le24 = |at: usize| Some(le32(at)? & 0x00ff_ffff);
+
+    match magic(data)? {
+        "image/png" => Some((be32(16)?, be32(20)?)),
+        "image/gif" => Some((le16(6)?, le16(8)?)),
+        // the height is negative for top-down bitmaps
+        "image/bmp" => Some((le32(18)?, (le32(22)? as i32).unsigned_abs())),
+        "image/webp" => match data.get(12..16)? {
+            b"VP8X" => Some((le24(24)? + 1, le24(27)? + 1)),
+            b"VP8 " => Some((le16(26)? & 0x3fff, le16(28)? & 0x3fff)),
+            b"VP8L" => {
+                let bits = le32(21)?;
+                Some(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1))
+            }
+            _ => None,
+        },
+        "image/jpeg" => {
+            // walk the segments up to the start of frame, holding the dimensions
+            let mut at = 2;
+            while *data.get(at)? == 0xff {
+                let marker = *data.get(at + 1)?;
+                if (0xc0..=0xcf).contains(&marker) && ![0xc4, 0xc8, 0xcc].contains(&marker) {
+                    return Some((be16(at + 7)?, be16(at + 5)?));
+                }
+                at += 2 + be16(at + 2)? as usize;
+            }
+            None
+        }
+        _ => None,
+    }
+}
+
+/// Whether a clipboard type is text, either a `text/*` MIME type or one of the X11 atoms.
+pub fn is_text(mime: &str) -> bool {
+    mime.starts_with("text/") || matches!(mime, "UTF8_STRING" | "STRING" | "TEXT")
+}
+
+/// Whether the MIME type is an image.
+pub fn is_image(mime: &str) -> bool {
+    mime.starts_with("image/")
+}
+
+/// MIME type of a file extension, [`OCTET_STREAM`] if unknown.
+pub fn from_extension(ext: &str) -> &'static str {
+    let ext = ext.to_lowercase();
+    SIGNATURES
+        .iter()
+        .map(|(mime, _, _, e)| (*mime, *e))
+        .chain(EXTENSIONS.iter().copied())
+        .find(|(_, e)| *e == ext)
+        .map(|(mime, _)| mime)
+        .unwrap_or(OCTET_STREAM)
+}
+
+/// Usual file extension of a MIME type, `bin` if unknown.
+pub fn extension(mime: &str) -> &'static str {
+    SIGNATURES
+        .iter()
+        .map(|(m, _, _, ext)| (*m, *ext))
+        .chain(EXTENSIONS.iter().copied())
+        .find(|(m, _)| *m == mime)
+        .map(|(_, ext)| ext)
+        .unwrap_or("bin")
+}
+
+/// Freedesktop icon name for a MIME type, e.g. `application-pdf` or `audio-x-generic`.
+pub fn icon_name(mime: &str) -> String {
+    match mime.split_once('/') {
+        Some((kind @ ("audio" | "video" | "font" | "image"), _)) => format!("{kind}-x-generic"),
+        Some(_) if mime != OCTET_STREAM => mime.replace('/', "-"),
+        _ => "application-x-generic".into(),
+    }
+}
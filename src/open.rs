@@ -0,0 +1,224 @@
+use std::{
+    env,
+    fs::{self, DirBuilder, OpenOptions},
+    io::Write,
+    os::unix::{
+        fs::{DirBuilderExt, OpenOptionsExt},
+        process::CommandExt,
+    },
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Context};
+use directories_next::BaseDirs;
+use log::{debug, trace, warn};
+
+use crate::{
+    classify::{self, Category},
+    config,
+    transform::shell_quote,
+};
+
+/// Age after which the images written for the viewer are removed.
+const STALE_IMAGE_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// What an entry is opened as.
+#[derive(Debug)]
+pub enum Target {
+    Url(String),
+    Path(PathBuf),
+    Image(PathBuf),
+}
+
+/// Opens entries using the configured commands, without waiting for them.
+pub struct Opener {
+    commands: config::Open,
+}
+
+pub fn new(commands: config::Open) -> Opener {
+    Opener { commands }
+}
+
+impl Target {
+    /// Target of a text: a URL, an e-mail address or the path of an existing file.
+    pub fn from_text(text: &str) -> Option<Target> {
+        let text = text.trim();
+        match classify::classify(text) {
+            Category::Url if text.starts_with("www.") => {
+                Some(Target::Url(format!("https://{text}")))
+            }
+            Category::Url => Some(Target::Url(text.into())),
+            Category::Email if text.starts_with("mailto:") => Some(Target::Url(text.into())),
+            Category::Email => Some(Target::Url(format!("mailto:{text}"))),
+            Category::Path => {
+                let path = match text.strip_prefix('~') {
+                    Some(rest) => {
+                        PathBuf::from(env::var("HOME").ok()?).join(rest.trim_start_matches('/'))
+                    }
+                    None => PathBuf::from(text),
+                };
+                path.exists().then_some(Target::Path(path))
+            }
+            _ => None,
+        }
+    }
+
+    /// Target of an image, written to a file only the user can read, in `$XDG_RUNTIME_DIR` (or
+    /// the cache directory). The images written by the previous runs are removed once stale.
+    pub fn image(id: &str, extension: &str, content: &[u8]) -> anyhow::Result<Target> {
+        let dirs = BaseDirs::new().context("Error getting base directories")?;
+        let dir = dirs
+            .runtime_dir()
+            .unwrap_or(dirs.cache_dir())
+            .join("rofi-cliphist")
+            .join("open");
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&dir)
+            .context(format!("Error creating {dir:?}"))?;
+        remove_stale(&dir);
+
+        let path = dir.join(format!("{id}.{extension}"));
+        trace!("Writing image to {path:?}");
+        // the image may have been opened before, a new file is created in any case
+        if let Err(e) = fs::remove_file(&path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                return Err(e).context(format!("Error removing {path:?}"));
+            }
+        }
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .and_then(|mut file| file.write_all(content))
+            .context(format!("Error writing {path:?}"))?;
+
+        Ok(Target::Image(path))
+    }
+}
+
+impl Opener {
+    /// Open a target using the command configured for its kind.
+    pub fn open(&self, target: &Target) -> anyhow::Result<()> {
+        let path = |path: &PathBuf| shell_quote(&path.to_string_lossy());
+        let command = match target {
+            Target::Url(url) => self.commands.url.replace("{url}", &shell_quote(url)),
+            Target::Path(p) if p.is_dir() => self.commands.directory.replace("{path}", &path(p)),
+            Target::Path(p) => self.commands.file.replace("{path}", &path(p)),
+            Target::Image(p) => self.commands.image.replace("{file}", &path(p)),
+        };
+        debug!("Opening {target:?}: {command}");
+
+        // run in the background of a short lived shell, so it outlives us without leaving a
+        // zombie, and in its own process group, so it isn't killed along with rofi
+        let status = Command::new("sh")
+            .args(["-c", &format!("(\n{command}\n) &")])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .process_group(0)
+            .status()
+            .context(format!("Error executing {command:?}"))?;
+        if !status.success() {
+            bail!("{command:?} exited with {status}");
+        }
+
+        Ok(())
+    }
+}
+
+/// Remove the files in `dir` older than [`STALE_IMAGE_AGE`].
+fn remove_stale(dir: &Path) {
+    let Ok(files) = fs::read_dir(dir) else {
+        return;
+    };
+    for file in files.flatten() {
+        let stale = file
+            .metadata()
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age > STALE_IMAGE_AGE);
+        if stale {
+            trace!("Removing stale image {:?}", file.path());
+            if let Err(e) = fs::remove_file(file.path()) {
+                warn!("Error removing {:?}: {e}", file.path());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(text: &str) -> Option<String> {
+        match Target::from_text(text) {
+            Some(Target::Url(url)) => Some(url),
+            target => panic!("unexpected target: {target:?}"),
+        }
+    }
+
+    fn path(text: &str) -> Option<PathBuf> {
+        match Target::from_text(text) {
+            Some(Target::Path(path)) => Some(path),
+            None => None,
+            target => panic!("unexpected target: {target:?}"),
+        }
+    }
+
+    #[test]
+    fn opens_urls() {
+        assert_eq!(
+            url("https://example.com/a?b=c").unwrap(),
+            "https://example.com/a?b=c"
+        );
+        assert_eq!(
+            url("  www.example.com\n").unwrap(),
+            "https://www.example.com"
+        );
+        assert_eq!(
+            url("file:///tmp/notes.txt").unwrap(),
+            "file:///tmp/notes.txt"
+        );
+        assert_eq!(url("me@example.com").unwrap(), "mailto:me@example.com");
+        assert_eq!(
+            url("mailto:me@example.com").unwrap(),
+            "mailto:me@example.com"
+        );
+    }
+
+    #[test]
+    fn opens_existing_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("notes.txt");
+        fs::write(&file, "notes").unwrap();
+
+        assert_eq!(path(&file.to_string_lossy()), Some(file.clone()));
+        assert_eq!(
+            path(&format!("{}\n", dir.path().display())),
+            Some(dir.path().into())
+        );
+        assert_eq!(path(&dir.path().join("missing").to_string_lossy()), None);
+    }
+
+    #[test]
+    fn expands_the_home_directory() {
+        let home = PathBuf::from(env::var("HOME").unwrap());
+
+        assert_eq!(path("~"), Some(home.clone()));
+        assert_eq!(path("~/"), Some(home.clone()));
+        assert_eq!(path("~/roto-missing-file"), None);
+    }
+
+    #[test]
+    fn ignores_plain_texts() {
+        assert!(Target::from_text("hello world").is_none());
+        assert!(Target::from_text("#ff8800").is_none());
+        assert!(Target::from_text("").is_none());
+    }
+}
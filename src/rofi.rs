@@ -4,12 +4,23 @@ use std::{
     process::{Command, Stdio},
 };
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use log::{debug, trace};
 
-use crate::{cache, cliphist::ClipHistEntry};
+use crate::{
+    action::MAX_CUSTOM_KBS,
+    cache::{self, CacheEntry},
+    classify::Category,
+    cliphist::{self, ClipHistEntry},
+    color::{self, Conversion},
+    history::pinned,
+    mime,
+    snippet::Snippet,
+    transform::Transform,
+};
 
 pub mod cliphist_mode;
+pub mod script;
 
 /// Entry to be displayed in rofi
 ///
@@ -17,55 +28,109 @@ pub mod cliphist_mode;
 /// Label is the text to be displayed.
 /// Icon is an optional icon to be displayed, either a path to a filesystem image o a supported
 /// pango icon name.
+///
+/// The remaining methods map to the optional rofi row options, see `rofi-script(5)`.
 pub trait RofiEntry {
     fn id(&self) -> String;
     fn label(&self) -> String;
     fn icon(&self) -> Option<String>;
+
+    /// Hidden search terms, matched but never displayed.
+    fn meta(&self) -> Option<String> {
+        None
+    }
+
+    /// Whether the row can't be selected, e.g. a header or separator.
+    fn nonselectable(&self) -> bool {
+        false
+    }
+
+    /// Extra information attached to the row, not displayed.
+    fn info(&self) -> Option<String> {
+        None
+    }
+
+    /// Whether the row is highlighted as urgent.
+    fn urgent(&self) -> bool {
+        false
+    }
+
+    /// Whether the row is highlighted as active.
+    fn active(&self) -> bool {
+        false
+    }
+
+    /// Text displayed instead of the label, which is still used for matching.
+    fn display(&self) -> Option<String> {
+        None
+    }
 }
 
-/// Possible result of a rofi execution
-pub enum RofiResult {
+/// Possible result of a rofi execution, `A` being the action bound to the custom keyboard
+/// shortcuts.
+pub enum RofiResult<A> {
     Cancel,
-    Empty,
-    Keyboard { key: i32, id: usize },
+    Empty,          // in script mode, also returned once the entries were handed over to rofi
+    Custom(String), // text typed by the user that matched no entry
+    Action { action: A, id: usize },
+    MultiAction { action: A, ids: Vec<usize> },
+    MultiSelection { ids: Vec<usize> },
     Selection { id: usize },
     Signal { key: i32 }, // just to capture OS signals, not sure whether it's useful
 }
 
-/// API to interact with rofi using command execution.
+/// API to interact with rofi, either spawning it in dmenu mode or acting as a script mode.
 pub struct Rofi {
     pub bin: String,
+    backend: Backend,
+}
+
+/// How roto talks to rofi.
+enum Backend {
+    /// Spawn `rofi -dmenu` on every run.
+    Dmenu,
+    /// Act as a rofi script (`rofi -modi name:script`), rofi is the one calling us.
+    Script(script::Script),
 }
 
+/// Exit code rofi uses for `-kb-custom-1`, the following ones are consecutive.
+const KB_CUSTOM_BASE_CODE: i32 = 10;
+
 /// Options to configure rofi when spawning it.
-pub struct RofiOptions {
-    format: Option<String>, // not expose format to foce using "i"
+pub struct RofiOptions<A = ()> {
+    format: Option<String>, // not expose format to foce using "i f"
     pub case_insensitive: bool,
-    pub custom_kbs: Vec<KbCustom>,
+    pub custom_kbs: Vec<KbCustom<A>>,
+    pub data: Option<String>, // opaque state kept between script mode callbacks
     pub dmenu: bool,
     pub mesg: Option<String>,
+    pub multi_select: bool,
     pub no_custom: bool,
     pub prompt: Option<String>,
     pub selected_row: usize,
     pub theme_str: Vec<String>,
 }
 
-/// Custom keyboard shortcuts for rofi.
-pub struct KbCustom {
+/// Custom keyboard shortcuts for rofi (`-kb-custom-{key}`), bound to the action returned when
+/// pressed.
+pub struct KbCustom<A> {
     key: i32,
     shortcut: String,
     description: String,
+    action: A,
 }
 
-impl Default for RofiOptions {
+impl<A> Default for RofiOptions<A> {
     fn default() -> Self {
         trace!("Creating default RofiOptions");
         RofiOptions {
             case_insensitive: true,
             custom_kbs: vec![],
+            data: None,
             dmenu: true,
-            format: Some("i".into()), // force return index instead of value
+            format: Some("i f".into()), // force return index (and typed text) instead of value
             mesg: None,
+            multi_select: false,
             no_custom: true,
             prompt: None,
             selected_row: 0,
@@ -74,7 +139,7 @@ impl Default for RofiOptions {
     }
 }
 
-impl RofiOptions {
+impl<A: Copy> RofiOptions<A> {
     pub fn new<I, K, S>(
         prompt: impl Into<String>,
         mesg: impl Into<String>,
@@ -84,7 +149,7 @@ impl RofiOptions {
     where
         I: IntoIterator<Item = S>,
         S: Into<String>,
-        K: IntoIterator<Item = KbCustom>,
+        K: IntoIterator<Item = KbCustom<A>>,
     {
         trace!("Creating RofiOptions");
         RofiOptions {
@@ -95,21 +160,47 @@ impl RofiOptions {
             ..Default::default()
         }
     }
+
+    /// Action bound to the custom shortcut rofi reported with the given exit code.
+    fn action(&self, code: i32) -> Option<A> {
+        self.custom_kbs
+            .iter()
+            .find(|kb| kb.key + KB_CUSTOM_BASE_CODE - 1 == code)
+            .map(|kb| kb.action)
+    }
 }
 
-impl KbCustom {
-    pub fn new(key: i32, shortcut: impl Into<String>, description: impl Into<String>) -> Self {
+impl<A> KbCustom<A> {
+    pub fn new(
+        key: i32,
+        shortcut: impl Into<String>,
+        description: impl Into<String>,
+        action: A,
+    ) -> Self {
         trace!("Creating KbCustom");
         KbCustom {
             key,
             shortcut: shortcut.into(),
             description: description.into(),
+            action,
         }
     }
+
+    pub fn shortcut(&self) -> &str {
+        &self.shortcut
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn action(&self) -> &A {
+        &self.action
+    }
 }
 
-impl From<&RofiOptions> for Vec<String> {
-    fn from(val: &RofiOptions) -> Self {
+impl<A> From<&RofiOptions<A>> for Vec<String> {
+    fn from(val: &RofiOptions<A>) -> Self {
         let mut options = Vec::new();
         options.push("-selected-row".into());
         options.push(val.selected_row.to_string());
@@ -122,6 +213,9 @@ impl From<&RofiOptions> for Vec<String> {
         if val.no_custom {
             options.push("-no-custom".into());
         }
+        if val.multi_select {
+            options.push("-multi-select".into());
+        }
         if let Some(format) = &val.format {
             options.push("-format".into());
             options.push(format.into());
@@ -130,17 +224,7 @@ impl From<&RofiOptions> for Vec<String> {
             options.push(format!("-kb-custom-{key}"));
             options.push(shortcut.into());
         }
-        if !&val.custom_kbs.is_empty() {
-            let mut mesg = String::from("<span size='small' alpha='70%'>");
-            for KbCustom {
-                shortcut,
-                description,
-                ..
-            } in &val.custom_kbs
-            {
-                mesg.push_str(format!("<b>{shortcut}</b>: {description} | ").as_str());
-            }
-            mesg.push_str("</span>");
+        if let Some(mesg) = kb_help(&val.custom_kbs) {
             options.push("-mesg".into());
             options.push(mesg);
         }
@@ -156,14 +240,71 @@ impl From<&RofiOptions> for Vec<String> {
     }
 }
 
+/// Help message listing the custom keyboard shortcuts, if any.
+fn kb_help<A>(custom_kbs: &[KbCustom<A>]) -> Option<String> {
+    if custom_kbs.is_empty() {
+        return None;
+    }
+
+    let mut mesg = String::from("<span size='small' alpha='70%'>");
+    for KbCustom {
+        shortcut,
+        description,
+        ..
+    } in custom_kbs
+    {
+        mesg.push_str(format!("<b>{shortcut}</b>: {description} | ").as_str());
+    }
+    mesg.push_str("</span>");
+
+    Some(mesg)
+}
+
 impl Rofi {
-    pub fn run(
+    /// Show the entries and wait for the user's choice.
+    ///
+    /// In script mode the first call returns what the user did before rofi called us (if anything),
+    /// further calls print the entries to stdout and return [`RofiResult::Empty`].
+    pub fn run<A: Copy>(
+        &mut self,
+        entries: &[&dyn RofiEntry],
+        options: &RofiOptions<A>,
+        cache: &cache::SimpleCache,
+    ) -> anyhow::Result<RofiResult<A>> {
+        if let Some(kb) = options
+            .custom_kbs
+            .iter()
+            .find(|kb| !(1..=MAX_CUSTOM_KBS as i32).contains(&kb.key))
+        {
+            bail!(
+                "Rofi supports custom shortcuts 1 to {MAX_CUSTOM_KBS}, got {} for '{}'",
+                kb.key,
+                kb.shortcut
+            );
+        }
+
+        match &mut self.backend {
+            Backend::Dmenu => self.run_dmenu(entries, options, cache),
+            Backend::Script(script) => script.run(entries, options, cache),
+        }
+    }
+
+    /// Opaque state saved by a previous script mode callback, always `None` in dmenu mode.
+    pub fn data(&self) -> Option<&str> {
+        match &self.backend {
+            Backend::Dmenu => None,
+            Backend::Script(script) => script.data(),
+        }
+    }
+
+    fn run_dmenu<A: Copy>(
         &self,
         entries: &[&dyn RofiEntry],
-        options: &RofiOptions,
+        options: &RofiOptions<A>,
         cache: &cache::SimpleCache,
-    ) -> anyhow::Result<RofiResult> {
-        let options = if entries.is_empty() {
+    ) -> anyhow::Result<RofiResult<A>> {
+        // with no entries but accepting typed text, rofi is used as an input prompt
+        let args = if entries.is_empty() && options.no_custom {
             let base_msg = "No clipboard entries to show".into();
             let error_msg = options
                 .prompt
@@ -172,40 +313,33 @@ impl Rofi {
                 .unwrap_or_else(|| base_msg);
             vec!["-e".into(), error_msg, "-markup".into()]
         } else {
-            options.into()
+            let mut args: Vec<String> = options.into();
+            for (flag, rows) in [
+                ("-u", state_rows(entries, |e| e.urgent())),
+                ("-a", state_rows(entries, |e| e.active())),
+            ] {
+                if let Some(rows) = rows {
+                    args.push(flag.into());
+                    args.push(rows);
+                }
+            }
+            args
         };
 
         let mut process = Command::new(&self.bin)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
-            .args(&options)
+            .args(&args)
             .spawn()
             .context("Error executing rofi")?;
 
-        debug!(
-            "Executing rofi with command: {:?} {:?}",
-            &self.bin, &options
-        );
+        debug!("Executing rofi with command: {:?} {:?}", &self.bin, &args);
 
         if let Some(mut writer) = process.stdin.take() {
             for entry in entries {
-                let mut str: Vec<u8> = Vec::new();
-                str.extend_from_slice(entry.label().to_string().as_bytes());
-                if let Some(icon) = &entry.icon() {
-                    let cached = cache.path(icon);
-                    if cached.exists() {
-                        str.extend_from_slice(
-                            format!("\0icon\x1f{}", cached.to_str().context("resolving icon")?)
-                                .as_bytes(),
-                        );
-                    } else {
-                        str.extend_from_slice(format!("\0icon\x1f{icon}").as_bytes());
-                    }
-                }
-                str.push(b'\n');
                 writer
-                    .write_all(&str)
+                    .write_all(&row(*entry, cache, &[])?)
                     .context("writing entries to rofi through stdin")?;
             }
         }
@@ -223,21 +357,32 @@ impl Rofi {
         }
 
         let result = if status.success() {
-            if buffer.is_empty() {
-                RofiResult::Empty
-            } else {
-                RofiResult::Selection {
-                    id: buffer.parse::<usize>().context("parsing usize")?,
-                }
+            match parse_output(&buffer)? {
+                Output::Custom(text) => RofiResult::Custom(text),
+                Output::Ids(ids) => match ids.as_slice() {
+                    [] => RofiResult::Empty,
+                    [id] => RofiResult::Selection { id: *id },
+                    ids => RofiResult::MultiSelection { ids: ids.to_vec() },
+                },
             }
         } else if let Some(code) = status.code() {
-            if buffer.is_empty() {
-                RofiResult::Cancel
-            } else {
-                RofiResult::Keyboard {
-                    key: code,
-                    id: buffer.parse::<usize>().context("parsing usize")?,
+            match parse_output(&buffer)? {
+                Output::Custom(text) => {
+                    trace!("Ignoring key {code} pressed without a selected entry: {text}");
+                    RofiResult::Cancel
                 }
+                Output::Ids(ids) => match (options.action(code), ids.as_slice()) {
+                    (_, []) => RofiResult::Cancel,
+                    (None, _) => {
+                        trace!("Ignoring unbound exit code: {code}");
+                        RofiResult::Cancel
+                    }
+                    (Some(action), [id]) => RofiResult::Action { action, id: *id },
+                    (Some(action), ids) => RofiResult::MultiAction {
+                        action,
+                        ids: ids.to_vec(),
+                    },
+                },
             }
         } else if let Some(code) = status.signal() {
             RofiResult::Signal { key: code }
@@ -251,29 +396,332 @@ impl Rofi {
     }
 }
 
+/// What rofi printed using the `i f` format.
+enum Output {
+    /// Indexes of the selected rows
+    Ids(Vec<usize>),
+    /// Text typed by the user, rofi reports it with the `-1` index
+    Custom(String),
+}
+
+/// Parse rofi's output, one `index filter` line per selected row (several when multi-select is
+/// enabled).
+fn parse_output(buffer: &str) -> anyhow::Result<Output> {
+    let mut ids = Vec::new();
+    for line in buffer.lines().filter(|line| !line.is_empty()) {
+        let (idx, filter) = line.split_once(' ').unwrap_or((line, ""));
+        if idx == "-1" {
+            return Ok(Output::Custom(filter.into()));
+        }
+        ids.push(idx.parse::<usize>().context("parsing usize")?);
+    }
+
+    Ok(Output::Ids(ids))
+}
+
+/// Indexes of the rows matching `predicate`, in the `-u`/`-a` dmenu format.
+fn state_rows(
+    entries: &[&dyn RofiEntry],
+    predicate: impl Fn(&dyn RofiEntry) -> bool,
+) -> Option<String> {
+    let rows = entries
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| predicate(**e))
+        .map(|(idx, _)| idx.to_string())
+        .collect::<Vec<_>>();
+
+    (!rows.is_empty()).then(|| rows.join(","))
+}
+
+/// Human readable size, e.g. `123 KiB`, the way cliphist prints it.
+pub fn human_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{size:.0} {}", units[unit])
+}
+
+/// Serialize an entry as a rofi row: the label followed by its row options.
+///
+/// `extra` are additional `(option, value)` pairs, overriding the ones provided by the entry.
+fn row(
+    entry: &dyn RofiEntry,
+    cache: &cache::SimpleCache,
+    extra: &[(&str, String)],
+) -> anyhow::Result<Vec<u8>> {
+    let mut options = Vec::new();
+    if let Some(icon) = &entry.icon() {
+        let cached = cache.path(icon);
+        if cached.exists() {
+            options.push(("icon", cached.to_str().context("resolving icon")?.into()));
+        } else {
+            options.push(("icon", icon.clone()));
+        }
+    }
+    if let Some(display) = entry.display() {
+        options.push(("display", display));
+    }
+    if let Some(meta) = entry.meta() {
+        options.push(("meta", meta));
+    }
+    if entry.nonselectable() {
+        options.push(("nonselectable", "true".into()));
+    }
+    if let Some(info) = entry.info() {
+        options.push(("info", info));
+    }
+    for (key, value) in extra {
+        options.retain(|(k, _)| k != key);
+        options.push((key, value.clone()));
+    }
+
+    let mut row: Vec<u8> = Vec::new();
+    // a new line would start another row, and `\0` the row options
+    row.extend_from_slice(entry.label().replace(['\n', '\r', '\0'], " ").as_bytes());
+    for (i, (key, value)) in options.iter().enumerate() {
+        // values can't contain the row or field separators
+        let value = value.replace(['\n', '\0', '\x1f'], " ");
+        row.push(if i == 0 { b'\0' } else { b'\x1f' });
+        row.extend_from_slice(format!("{key}\x1f{value}").as_bytes());
+    }
+    row.push(b'\n');
+
+    Ok(row)
+}
+
 impl RofiEntry for ClipHistEntry {
     fn id(&self) -> String {
         match self {
             ClipHistEntry::Text { id, .. } => id.into(),
             ClipHistEntry::Image { id, .. } => id.into(),
+            ClipHistEntry::Binary { id, .. } => id.into(),
         }
     }
+    fn active(&self) -> bool {
+        pinned::is_pinned(&RofiEntry::id(self))
+    }
     fn icon(&self) -> Option<String> {
         match self {
-            ClipHistEntry::Text { .. } => None,
-            ClipHistEntry::Image { id, content_type } => Some(format!("{id}.{content_type}")),
+            ClipHistEntry::Text {
+                title, category, ..
+            } => {
+                // colors show a swatch, generated in the cache
+                let swatch = match category {
+                    Category::Color => color::parse(title),
+                    _ => None,
+                };
+                Some(swatch.map_or_else(|| category.icon().into(), |color| color.id()))
+            }
+            ClipHistEntry::Image {
+                id, content_type, ..
+            } => Some(format!("{id}.{content_type}")),
+            ClipHistEntry::Binary { mime, .. } => Some(mime::icon_name(mime)),
+        }
+    }
+    fn meta(&self) -> Option<String> {
+        match self {
+            // typing `url:` only shows the URLs
+            ClipHistEntry::Text { category, .. } => Some(format!("{}:", category.name())),
+            // plain `x` so dimensions can be typed, along with the full MIME type
+            ClipHistEntry::Image {
+                content_type,
+                dimensions,
+                ..
+            } => {
+                let mut meta = mime::from_extension(content_type).to_string();
+                if let Some((width, height)) = dimensions {
+                    meta.push_str(&format!(" {width}x{height}"));
+                }
+                Some(meta)
+            }
+            ClipHistEntry::Binary { mime, .. } => Some(mime.into()),
         }
     }
     fn label(&self) -> String {
         match self {
             ClipHistEntry::Text { title, .. } => title.into(),
-            ClipHistEntry::Image { id, content_type } => {
-                format!("{id}.{content_type}")
+            ClipHistEntry::Image {
+                content_type,
+                size,
+                dimensions,
+                ..
+            } => {
+                let mut parts = vec![content_type.to_string()];
+                if let Some((width, height)) = dimensions {
+                    parts.push(format!("{width}×{height}"));
+                }
+                if *size > 0 {
+                    parts.push(human_size(*size));
+                }
+                parts.join(" · ")
             }
+            ClipHistEntry::Binary { mime, size: 0, .. } => mime.into(),
+            ClipHistEntry::Binary { mime, size, .. } => format!("{mime} · {}", human_size(*size)),
         }
     }
 }
 
+impl RofiEntry for Snippet {
+    fn id(&self) -> String {
+        self.name.clone()
+    }
+    fn icon(&self) -> Option<String> {
+        None
+    }
+    fn label(&self) -> String {
+        format!("{} · {}", self.name, cliphist::preview(&self.text))
+    }
+}
+
+impl RofiEntry for Transform {
+    fn id(&self) -> String {
+        self.name().into()
+    }
+    fn icon(&self) -> Option<String> {
+        None
+    }
+    fn label(&self) -> String {
+        self.name().into()
+    }
+    fn meta(&self) -> Option<String> {
+        match self {
+            Transform::Builtin(_) => None,
+            Transform::Filter(filter) => Some(filter.command.clone()),
+        }
+    }
+}
+
+impl RofiEntry for Conversion {
+    fn id(&self) -> String {
+        self.notation.clone()
+    }
+    fn icon(&self) -> Option<String> {
+        None
+    }
+    fn label(&self) -> String {
+        self.value.clone()
+    }
+    fn meta(&self) -> Option<String> {
+        Some(self.notation.clone())
+    }
+}
+
+/// Create a rofi api that spawns `rofi -dmenu`.
 pub fn new(bin: impl Into<String>) -> Rofi {
-    Rofi { bin: bin.into() }
+    Rofi {
+        bin: bin.into(),
+        backend: Backend::Dmenu,
+    }
+}
+
+/// Create a rofi api that speaks the script mode protocol, reading rofi's callback from the
+/// environment.
+///
+/// `input` is the argument rofi passes to the script: the selected entry or the typed text.
+pub fn script(bin: impl Into<String>, input: Option<String>) -> anyhow::Result<Rofi> {
+    Ok(Rofi {
+        bin: bin.into(),
+        backend: Backend::Script(script::Script::from_env(input)?),
+    })
+}
+
+/// Whether the current process was launched by rofi as a script mode.
+pub fn is_script_mode() -> bool {
+    std::env::var_os(script::ROFI_RETV).is_some()
+}
+
+/// Whether rofi called the script mode back after a user interaction, rather than to open the
+/// menu.
+pub fn is_callback() -> bool {
+    std::env::var(script::ROFI_RETV).is_ok_and(|retv| retv != "0")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Entry {
+        label: &'static str,
+        meta: Option<&'static str>,
+    }
+
+    impl RofiEntry for Entry {
+        fn id(&self) -> String {
+            self.label.into()
+        }
+        fn label(&self) -> String {
+            self.label.into()
+        }
+        fn icon(&self) -> Option<String> {
+            None
+        }
+        fn meta(&self) -> Option<String> {
+            self.meta.map(Into::into)
+        }
+    }
+
+    fn ids(output: Output) -> Vec<usize> {
+        match output {
+            Output::Ids(ids) => ids,
+            Output::Custom(text) => panic!("unexpected custom input: {text}"),
+        }
+    }
+
+    fn custom(output: Output) -> String {
+        match output {
+            Output::Custom(text) => text,
+            Output::Ids(ids) => panic!("unexpected selection: {ids:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_selected_rows() {
+        assert_eq!(ids(parse_output("3 \n").unwrap()), vec![3]);
+        assert_eq!(
+            ids(parse_output("0 foo\n2 foo\n5 foo\n").unwrap()),
+            vec![0, 2, 5]
+        );
+        assert_eq!(ids(parse_output("1").unwrap()), vec![1]);
+        assert!(ids(parse_output("").unwrap()).is_empty());
+        assert!(parse_output("x foo\n").is_err());
+    }
+
+    #[test]
+    fn parses_custom_input() {
+        assert_eq!(
+            custom(parse_output("-1 hello world\n").unwrap()),
+            "hello world"
+        );
+        assert_eq!(custom(parse_output("-1  padded \n").unwrap()), " padded ");
+        assert_eq!(custom(parse_output("-1 \n").unwrap()), "");
+        assert_eq!(custom(parse_output("-1").unwrap()), "");
+    }
+
+    #[test]
+    fn keeps_rows_on_a_single_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache::SimpleCache::at(dir.path().into());
+
+        let entry = Entry {
+            label: "a\nb\0c\r",
+            meta: Some("x\ny\x1fz"),
+        };
+        assert_eq!(
+            row(&entry, &cache, &[]).unwrap(),
+            b"a b c \0meta\x1fx y z\n"
+        );
+
+        let entry = Entry {
+            label: "plain",
+            meta: None,
+        };
+        let extra = [("info", "7".to_string())];
+        assert_eq!(row(&entry, &cache, &extra).unwrap(), b"plain\0info\x1f7\n");
+    }
 }
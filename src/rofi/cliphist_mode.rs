@@ -1,188 +1,502 @@
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+};
+
 use anyhow::{bail, Context};
-use log::{debug, trace};
+use log::{debug, trace, warn};
+use serde::{Deserialize, Serialize};
 
 use crate::{
+    action::Action,
     cache::{CacheEntry, SimpleCache},
+    classify::Category,
     clipboard::Clipboard,
-    cliphist::{ClipHist, ClipHistEntry},
+    cliphist::ClipHistEntry,
+    color::{self, Conversion},
     config,
-    rofi::{self, RofiEntry},
+    history::{
+        pinned::{self, Pinned},
+        HistoryBackend,
+    },
+    index::TextIndex,
+    launcher::Launcher,
+    open::{Opener, Target},
+    retention::Policy,
+    rofi::RofiEntry,
+    secret::Secrets,
+    snippet::Snippet,
+    transform::Transform,
 };
 
-use super::{KbCustom, Rofi, RofiOptions, RofiResult};
+use super::{KbCustom, RofiOptions, RofiResult};
 
 /// Current mode to display
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 enum Mode {
     Text,
     Image,
+    Binary,
+    Pinned,
+    Snippets,
+}
+
+impl Mode {
+    /// Name used to remember the mode between rofi script callbacks.
+    fn name(&self) -> &'static str {
+        match self {
+            Mode::Text => "text",
+            Mode::Image => "image",
+            Mode::Binary => "binary",
+            Mode::Pinned => "pinned",
+            Mode::Snippets => "snippets",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(Mode::Text),
+            "image" => Some(Mode::Image),
+            "binary" => Some(Mode::Binary),
+            "pinned" => Some(Mode::Pinned),
+            "snippets" => Some(Mode::Snippets),
+            _ => None,
+        }
+    }
+}
+
+/// Order of the image and file entries
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Sort {
+    Newest,
+    Largest,
+    Resolution,
+}
+
+impl Sort {
+    fn name(&self) -> &'static str {
+        match self {
+            Sort::Newest => "newest",
+            Sort::Largest => "largest",
+            Sort::Resolution => "resolution",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "newest" => Some(Sort::Newest),
+            "largest" => Some(Sort::Largest),
+            "resolution" => Some(Sort::Resolution),
+            _ => None,
+        }
+    }
+
+    /// Next order of the cycle, files having no resolution.
+    fn next(&self, mode: Mode) -> Self {
+        match (self, mode) {
+            (Sort::Newest, _) => Sort::Largest,
+            (Sort::Largest, Mode::Binary) => Sort::Newest,
+            (Sort::Largest, _) => Sort::Resolution,
+            (Sort::Resolution, _) => Sort::Newest,
+        }
+    }
+
+    /// Sort the entries, `history` giving the position of each id in the history (newest first).
+    /// Pinned entries always stay at the top.
+    fn apply(&self, entries: &mut [ClipHistEntry], history: &HashMap<String, usize>) {
+        let unpinned = |e: &ClipHistEntry| !pinned::is_pinned(&RofiEntry::id(e));
+        match self {
+            Sort::Newest => entries.sort_by_key(|e| history.get(&RofiEntry::id(e)).copied()),
+            Sort::Largest => entries.sort_by_key(|e| match e {
+                ClipHistEntry::Image { size, .. } | ClipHistEntry::Binary { size, .. } => {
+                    (unpinned(e), Reverse(*size))
+                }
+                ClipHistEntry::Text { .. } => (unpinned(e), Reverse(0)),
+            }),
+            Sort::Resolution => entries.sort_by_key(|e| match e {
+                ClipHistEntry::Image {
+                    dimensions: Some((width, height)),
+                    ..
+                } => (unpinned(e), Reverse(*width as u64 * *height as u64)),
+                _ => (unpinned(e), Reverse(0)),
+            }),
+        }
+    }
+}
+
+impl Action {
+    /// Mode the action switches to, if it's a mode switch.
+    fn mode(&self) -> Option<Mode> {
+        match self {
+            Action::TextMode => Some(Mode::Text),
+            Action::ImageMode => Some(Mode::Image),
+            Action::BinaryMode => Some(Mode::Binary),
+            Action::PinnedMode => Some(Mode::Pinned),
+            Action::SnippetsMode => Some(Mode::Snippets),
+            _ => None,
+        }
+    }
+
+    /// Whether the action makes sense in the given mode, e.g. no need to switch to the current one.
+    fn applies_to(&self, mode: Mode) -> bool {
+        match (self, mode) {
+            // snippets can only be copied
+            (Action::Copy, Mode::Snippets) => true,
+            (action, Mode::Snippets) if action.mode().is_none() => false,
+            (Action::MoveUp | Action::MoveDown, mode) => matches!(mode, Mode::Pinned),
+            (Action::Transform | Action::ConvertColor | Action::Dedupe, mode) => {
+                matches!(mode, Mode::Text)
+            }
+            (Action::Open, mode) => matches!(mode, Mode::Text | Mode::Image | Mode::Pinned),
+            (Action::Sort, mode) => matches!(mode, Mode::Image | Mode::Binary),
+            (
+                Action::Delete | Action::DeletePrevious | Action::DeleteNext | Action::Pin,
+                Mode::Pinned,
+            ) => false,
+            (action, mode) => !matches!(
+                (action, mode),
+                (Action::TextMode, Mode::Text)
+                    | (Action::ImageMode, Mode::Image)
+                    | (Action::BinaryMode, Mode::Binary)
+                    | (Action::PinnedMode, Mode::Pinned)
+                    | (Action::SnippetsMode, Mode::Snippets)
+            ),
+        }
+    }
+}
+
+/// What to do once the user's choice was handled
+enum Next {
+    Show,
+    Exit,
 }
 
 /// Configuration for the ClipHistMode
 pub struct ClipHistModeConfig {
-    pub text_mode: config::ModeConfig,
-    pub image_mode: config::ModeConfig,
-    pub delete_mode: config::ModeConfig,
-    pub delete_previous_mode: config::ModeConfig,
-    pub delete_next_mode: config::ModeConfig,
+    /// Custom shortcuts, the position gives the rofi `-kb-custom-N` slot
+    pub keybindings: Vec<config::Keybinding>,
+    /// Separator used to join several text entries copied at once
+    pub separator: String,
+    /// Snippets listed in the snippets mode
+    pub snippets: Vec<Snippet>,
+    /// Transforms listed by the transform action
+    pub transforms: Vec<Transform>,
+    /// Whether transformed texts are also stored as new history entries
+    pub store_transformed: bool,
+    /// Opens URLs, paths and images
+    pub opener: Opener,
+    /// Masks and expires passwords and tokens, if enabled
+    pub secrets: Option<Secrets>,
+    /// Retention rules enforced at startup, if any
+    pub retention: Option<Policy>,
+    /// Whether texts only differing by leading and trailing whitespace are listed once
+    pub dedupe: bool,
+    /// Full content of the cut off texts, searched along with their preview, if enabled
+    pub index: Option<TextIndex>,
 }
 
 /// A rofi "mode" to display the clipboard history
-/// It keeps an internal state and spawns rofi to display the entries
+/// It keeps an internal state and spawns the launcher (rofi by default) to display the entries
 pub struct ClipHistMode {
-    rofi: Rofi,
+    launcher: Box<dyn Launcher<Action>>,
     cache: SimpleCache,
-    cliphist: ClipHist,
+    history: Box<dyn HistoryBackend>,
+    pinned: Pinned,
     clipboard: Clipboard,
     txt: RofiState,
     img: RofiState,
+    bin: RofiState,
+    pin: RofiState,
+    snip: RofiState<Snippet>,
+    transforms: Vec<Transform>,
+    store_transformed: bool,
+    opener: Opener,
+    secrets: Option<Secrets>,
+    /// Menu shown instead of the current mode to complete the user's choice
+    follow_up: Option<FollowUp>,
     mode: Mode,
+    sort: Sort,
+    /// Position of each entry in the history (pinned ones first), to restore the newest first
+    /// order
+    order: HashMap<String, usize>,
+    separator: String,
+    dedupe: bool,
+    /// Older copies of the listed texts, by the id of the newest one, when deduping
+    copies: HashMap<String, Vec<String>>,
+    index: Option<TextIndex>,
+}
+
+/// Rows of the entries before (`DeletePrevious`) or after (`DeleteNext`) the selected rows in the
+/// history, whatever the entries are sorted by. Pinned entries are never deleted, so they're left
+/// out.
+fn surrounding(
+    entries: &[ClipHistEntry],
+    order: &HashMap<String, usize>,
+    selected: &[usize],
+    action: Action,
+) -> Vec<usize> {
+    let position = |entry: &ClipHistEntry| order.get(&RofiEntry::id(entry)).copied();
+    let selected = selected
+        .iter()
+        .filter_map(|row| entries.get(*row))
+        .filter_map(position)
+        .collect::<Vec<_>>();
+    let (newest, oldest) = (selected.iter().min(), selected.iter().max());
+
+    (0..entries.len())
+        .filter(|row| !pinned::is_pinned(&RofiEntry::id(&entries[*row])))
+        .filter(
+            |row| match (action, position(&entries[*row]), newest, oldest) {
+                (Action::DeletePrevious, Some(pos), Some(newest), _) => pos < *newest,
+                (Action::DeleteNext, Some(pos), _, Some(oldest)) => pos > *oldest,
+                _ => false,
+            },
+        )
+        .collect()
 }
 
-struct RofiState {
-    entries: Vec<ClipHistEntry>,
-    options: RofiOptions,
+/// A text as listed: once for all its copies, showing how many there are, and searchable by its
+/// full content when indexed.
+struct TextRow<'a> {
+    entry: &'a ClipHistEntry,
+    copies: usize,
+    text: Option<&'a str>,
+}
+
+impl RofiEntry for TextRow<'_> {
+    fn id(&self) -> String {
+        RofiEntry::id(self.entry)
+    }
+    fn icon(&self) -> Option<String> {
+        self.entry.icon()
+    }
+    fn meta(&self) -> Option<String> {
+        match (self.entry.meta(), self.text) {
+            (Some(meta), Some(text)) => Some(format!("{meta} {text}")),
+            (meta, text) => meta.or(text.map(Into::into)),
+        }
+    }
+    fn active(&self) -> bool {
+        self.entry.active()
+    }
+    fn label(&self) -> String {
+        match self.copies {
+            0 => self.entry.label(),
+            copies => format!("{} ×{}", self.entry.label(), copies + 1),
+        }
+    }
+}
+
+struct RofiState<E = ClipHistEntry> {
+    entries: Vec<E>,
+    options: RofiOptions<Action>,
+}
+
+/// Menu completing the user's choice, saved in the launcher's data between script mode callbacks.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum Pending {
+    /// Input prompt asking the value of a snippet's variable, `values` being the ones typed so far
+    /// in the order of [`Snippet::variables`]
+    Prompt { snippet: usize, values: Vec<String> },
+    /// Menu listing the transforms that can be applied to an entry
+    Transform { id: String },
+    /// Menu listing a color written in the supported notations
+    Color { conversions: Vec<Conversion> },
+}
+
+struct FollowUp {
+    pending: Pending,
+    options: RofiOptions<Action>,
 }
 
 impl ClipHistMode {
     /// Create a new instance of ClipHistMode
     pub fn new(
-        rofi: rofi::Rofi,
+        launcher: Box<dyn Launcher<Action>>,
         cache: SimpleCache,
-        cliphist: ClipHist,
+        history: Box<dyn HistoryBackend>,
+        pinned: Pinned,
         clipboard: Clipboard,
         config: ClipHistModeConfig,
     ) -> anyhow::Result<Self> {
         trace!("Creating ClipHistMode");
 
-        let (txt, img): (Vec<_>, Vec<_>) = cliphist
-            .list()
-            .context("Error listing cliphist")?
-            .into_iter()
-            .partition(|e| matches!(e, ClipHistEntry::Text { .. }));
+        // the position in the registry gives the rofi `-kb-custom-N` slot, so it stays the
+        // same whatever the mode is (rofi script mode can't rebind them)
+        let registry = config.keybindings;
+
+        // saved as `mode`, `mode:sort` or, while showing a follow-up menu, `mode:sort:pending`
+        let data = launcher.data().unwrap_or_default();
+        let mut parts = data.splitn(3, ':');
+        let mode = Mode::from_name(parts.next().unwrap_or_default()).unwrap_or(Mode::Text);
+        let sort = Sort::from_name(parts.next().unwrap_or_default()).unwrap_or(Sort::Newest);
+        let pending = parts
+            .next()
+            .and_then(|pending| serde_json::from_str::<Pending>(pending).ok());
 
-        let delete_shortcut = &config.delete_mode.shortcut;
-        let delete_description = &config.delete_mode.description;
-        let instance = Self {
-            rofi,
+        // best effort, failing to prune shouldn't prevent using the history
+        if let Some(policy) = &config.retention {
+            match policy.prune(history.as_ref()) {
+                Ok(pruned) => debug!("Pruned {} entries", pruned.len()),
+                Err(e) => warn!("Error applying retention rules: {e:?}"),
+            }
+        }
+        let mut instance = Self {
+            launcher,
             cache,
-            cliphist,
+            history,
+            pinned,
             clipboard,
             txt: RofiState {
-                entries: txt,
+                entries: vec![],
                 options: RofiOptions::new(
                     Self::title(Mode::Text),
                     "",
-                    [
-                        KbCustom::new(1, config.image_mode.shortcut, config.image_mode.description),
-                        KbCustom::new(3, delete_shortcut, delete_description),
-                        KbCustom::new(
-                            4,
-                            &config.delete_previous_mode.shortcut,
-                            config.delete_previous_mode.description.clone(),
-                        ),
-                        KbCustom::new(
-                            5,
-                            &config.delete_next_mode.shortcut,
-                            config.delete_next_mode.description.clone(),
-                        ),
-                    ],
+                    Self::custom_kbs(&registry, Mode::Text),
                     Self::theme(Mode::Text),
                 ),
             },
             img: RofiState {
-                entries: img,
+                entries: vec![],
                 options: RofiOptions::new(
                     Self::title(Mode::Image),
                     "",
-                    [
-                        KbCustom::new(2, config.text_mode.shortcut, config.text_mode.description),
-                        KbCustom::new(3, delete_shortcut, delete_description),
-                        KbCustom::new(
-                            4,
-                            &config.delete_previous_mode.shortcut,
-                            config.delete_previous_mode.description.clone(),
-                        ),
-                        KbCustom::new(
-                            5,
-                            &config.delete_next_mode.shortcut,
-                            config.delete_next_mode.description.clone(),
-                        ),
-                    ],
+                    Self::custom_kbs(&registry, Mode::Image),
                     Self::theme(Mode::Image),
                 ),
             },
-            mode: Mode::Text,
+            bin: RofiState {
+                entries: vec![],
+                options: RofiOptions::new(
+                    Self::title(Mode::Binary),
+                    "",
+                    Self::custom_kbs(&registry, Mode::Binary),
+                    Self::theme(Mode::Binary),
+                ),
+            },
+            pin: RofiState {
+                entries: vec![],
+                options: RofiOptions::new(
+                    Self::title(Mode::Pinned),
+                    "",
+                    Self::custom_kbs(&registry, Mode::Pinned),
+                    Self::theme(Mode::Pinned),
+                ),
+            },
+            snip: RofiState {
+                entries: config.snippets,
+                options: RofiOptions::new(
+                    Self::title(Mode::Snippets),
+                    "",
+                    Self::custom_kbs(&registry, Mode::Snippets),
+                    Self::theme(Mode::Snippets),
+                ),
+            },
+            transforms: config.transforms,
+            store_transformed: config.store_transformed,
+            opener: config.opener,
+            secrets: config.secrets,
+            follow_up: None,
+            mode,
+            sort,
+            order: HashMap::new(),
+            separator: config.separator,
+            dedupe: config.dedupe,
+            copies: HashMap::new(),
+            index: config.index,
         };
+        instance.txt.options.multi_select = true;
+        instance.txt.options.no_custom = false;
+        instance.img.options.multi_select = true;
+        instance.bin.options.multi_select = true;
+        instance.pin.options.multi_select = true;
+        instance.reload()?;
+        if let Some(pending) = pending {
+            instance.ask(pending)?;
+        }
 
         Ok(instance)
     }
 
-    /// This is the "main loop" of the mode
+    /// This is the "main loop" of the mode.
+    ///
+    /// When rofi runs us as a script mode it returns as soon as the entries were handed over.
     pub fn run(&mut self) -> anyhow::Result<()> {
         debug!("Running ClipHistMode");
 
         loop {
             self.sync_cache()?;
 
-            let current = match self.mode {
-                Mode::Text => &mut self.txt,
-                Mode::Image => &mut self.img,
-            };
-
-            let entries = current
+            let texts = self
+                .txt
                 .entries
                 .iter()
-                .map(|e| e as &dyn rofi::RofiEntry)
+                .map(|entry| {
+                    let id = RofiEntry::id(entry);
+                    TextRow {
+                        copies: self.copies.get(&id).map_or(0, Vec::len),
+                        text: self.index.as_ref().and_then(|index| index.get(&id)),
+                        entry,
+                    }
+                })
                 .collect::<Vec<_>>();
+            let (entries, options) = match (&self.follow_up, self.mode) {
+                (Some(follow_up), _) => match follow_up.pending {
+                    Pending::Prompt { .. } => (vec![], &follow_up.options),
+                    Pending::Transform { .. } => (Self::rows(&self.transforms), &follow_up.options),
+                    Pending::Color { ref conversions } => {
+                        (Self::rows(conversions), &follow_up.options)
+                    }
+                },
+                (None, Mode::Text) => (Self::rows(&texts), &self.txt.options),
+                (None, Mode::Image) => (Self::rows(&self.img.entries), &self.img.options),
+                (None, Mode::Binary) => (Self::rows(&self.bin.entries), &self.bin.options),
+                (None, Mode::Pinned) => (Self::rows(&self.pin.entries), &self.pin.options),
+                (None, Mode::Snippets) => (Self::rows(&self.snip.entries), &self.snip.options),
+            };
 
-            match self
-                .rofi
-                .run(&entries, &current.options, &self.cache)
-                .context("running rofi")?
-            {
-                RofiResult::Selection { id } => {
-                    current.options.selected_row = id;
-
-                    let entry = current.entries.get(id).expect("Invalid id");
-                    let cliphist_id = RofiEntry::id(entry);
-                    self.clipboard.copy(
-                        self.cliphist
-                            .value_of(cliphist_id)
-                            .context("Error getting cliphist entry")?,
-                    )?;
+            let result = self
+                .launcher
+                .run(&entries, options, &self.cache)
+                .context("running launcher")?;
+
+            if let Some(FollowUp { pending, .. }) = self.follow_up.take() {
+                if let Next::Exit = self.on_follow_up(pending, result)? {
                     return Ok(());
                 }
-                RofiResult::Keyboard { key, id } => {
-                    current.options.selected_row = id;
-
-                    match key {
-                        10 => {
-                            self.mode = Mode::Image;
-                        }
-                        11 => {
-                            self.mode = Mode::Text;
-                        }
-                        12 => {
-                            let entry = current.entries.remove(id);
-                            self.cliphist.remove(RofiEntry::id(&entry))?;
-                        }
-                        13 => {
-                            let entries_to_delete = current.entries.drain(..id).collect::<Vec<_>>();
-                            for entry in entries_to_delete {
-                                self.cliphist.remove(RofiEntry::id(&entry))?;
-                            }
-                        }
-                        14 => {
-                            let entries_to_delete =
-                                current.entries.drain(id + 1..).collect::<Vec<_>>();
-                            for entry in entries_to_delete {
-                                self.cliphist.remove(RofiEntry::id(&entry))?;
-                            }
-                        }
-                        _ => bail!("Unexpected key: {}", key),
+                continue;
+            }
+
+            match result {
+                RofiResult::Selection { id } => {
+                    if let Next::Exit = self.copy(&[id])? {
+                        return Ok(());
+                    }
+                }
+                RofiResult::MultiSelection { ids } => {
+                    if let Next::Exit = self.copy(&ids)? {
+                        return Ok(());
                     }
                 }
+                RofiResult::Action { action, id } => {
+                    if let Next::Exit = self.on_action(action, &[id])? {
+                        return Ok(());
+                    }
+                }
+                RofiResult::MultiAction { action, ids } => {
+                    if let Next::Exit = self.on_action(action, &ids)? {
+                        return Ok(());
+                    }
+                }
+                RofiResult::Custom(text) => {
+                    // typed text that matches no entry becomes a new one
+                    self.clipboard.copy(text.clone().into_bytes())?;
+                    self.history
+                        .store(text.into_bytes())
+                        .context("Error storing history entry")?;
+                    return Ok(());
+                }
                 RofiResult::Cancel => {
                     // just inform the user and exit
                     trace!("Cancelled");
@@ -202,11 +516,570 @@ impl ClipHistMode {
         }
     }
 
+    /// Rows as shown by the launcher.
+    fn rows<E: RofiEntry>(entries: &[E]) -> Vec<&dyn RofiEntry> {
+        entries
+            .iter()
+            .map(|e| e as &dyn RofiEntry)
+            .collect::<Vec<_>>()
+    }
+
+    /// Show a follow-up menu on the next round instead of the current mode.
+    fn ask(&mut self, pending: Pending) -> anyhow::Result<()> {
+        let (prompt, no_custom) = match &pending {
+            Pending::Prompt { snippet, values } => {
+                let Some(snippet) = self.snip.entries.get(*snippet) else {
+                    bail!("Invalid snippet: {snippet}");
+                };
+                let Some(name) = snippet.variables().get(values.len()).copied() else {
+                    bail!("No variable left to fill in snippet {}", snippet.name);
+                };
+                trace!("Asking for variable {name} of snippet {}", snippet.name);
+                (format!("{} · {name}", snippet.name), false)
+            }
+            Pending::Transform { .. } => ("Transform".to_string(), true),
+            Pending::Color { .. } => ("Convert color".to_string(), true),
+        };
+
+        let mut options = RofiOptions::new(prompt, "", vec![], Self::theme(Mode::Snippets));
+        options.no_custom = no_custom;
+        options.data = Some(format!(
+            "{}:{}:{}",
+            self.mode.name(),
+            self.sort.name(),
+            serde_json::to_string(&pending).context("Error saving follow-up menu")?
+        ));
+        self.follow_up = Some(FollowUp { pending, options });
+
+        Ok(())
+    }
+
+    /// Handle the user's choice in a follow-up menu, anything but completing it exits.
+    fn on_follow_up(
+        &mut self,
+        pending: Pending,
+        result: RofiResult<Action>,
+    ) -> anyhow::Result<Next> {
+        match (pending, result) {
+            (
+                Pending::Prompt {
+                    snippet,
+                    mut values,
+                },
+                RofiResult::Custom(text),
+            ) => {
+                values.push(text);
+                self.fill(snippet, values)
+            }
+            (Pending::Transform { id }, RofiResult::Selection { id: idx }) => {
+                self.transform(&id, idx)
+            }
+            (Pending::Color { mut conversions }, RofiResult::Selection { id: idx }) => {
+                if idx >= conversions.len() {
+                    bail!("Invalid color conversion: {idx}");
+                }
+                let conversion = conversions.swap_remove(idx);
+                self.clipboard.copy(conversion.value.into_bytes())?;
+                Ok(Next::Exit)
+            }
+            (pending, _) => {
+                trace!("Follow-up menu dismissed: {pending:?}");
+                Ok(Next::Exit)
+            }
+        }
+    }
+
+    /// Copy the selected entries to the clipboard.
+    ///
+    /// Several text entries are joined using the configured separator, images and files can't be
+    /// merged so only the first one is copied. Snippets may first ask for the value of their
+    /// variables.
+    fn copy(&mut self, ids: &[usize]) -> anyhow::Result<Next> {
+        let current = match self.mode {
+            Mode::Text => &mut self.txt,
+            Mode::Image => &mut self.img,
+            Mode::Binary => &mut self.bin,
+            Mode::Pinned => &mut self.pin,
+            Mode::Snippets => {
+                self.snip.options.selected_row = ids[0];
+                return self.fill(ids[0], vec![]);
+            }
+        };
+        current.options.selected_row = ids[0];
+
+        let entries = ids
+            .iter()
+            .map(|id| {
+                current
+                    .entries
+                    .get(*id)
+                    .context(format!("Invalid id: {id}"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let all_text = entries
+            .iter()
+            .all(|e| matches!(e, ClipHistEntry::Text { .. }));
+        let mut targets = entries.into_iter().map(RofiEntry::id).collect::<Vec<_>>();
+        if !all_text {
+            debug!("Only the first of {} selected entries is copied", ids.len());
+            targets.truncate(1);
+        }
+
+        let values = targets
+            .iter()
+            .map(|id| {
+                self.backend(id)
+                    .decode(id)
+                    .context("Error getting history entry")
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        self.clipboard
+            .copy(values.join(self.separator.as_bytes()))
+            .map(|_| Next::Exit)
+    }
+
+    /// Copy a snippet once all its variables have a value, asking for the next one otherwise.
+    fn fill(&mut self, idx: usize, values: Vec<String>) -> anyhow::Result<Next> {
+        let Some(snippet) = self.snip.entries.get(idx) else {
+            bail!("Invalid snippet: {idx}");
+        };
+
+        if snippet.variables().len() > values.len() {
+            self.ask(Pending::Prompt {
+                snippet: idx,
+                values,
+            })?;
+            return Ok(Next::Show);
+        }
+
+        let text = snippet.expand(&values, || self.newest_text())?;
+        self.clipboard.copy(text.into_bytes())?;
+
+        Ok(Next::Exit)
+    }
+
+    /// Copy an entry once transformed, storing the result in the history if configured to.
+    fn transform(&mut self, id: &str, idx: usize) -> anyhow::Result<Next> {
+        let Some(transform) = self.transforms.get(idx) else {
+            bail!("Invalid transform: {idx}");
+        };
+
+        let content = self
+            .backend(id)
+            .decode(id)
+            .context("Error getting history entry")?;
+        let content = transform
+            .apply(content)
+            .context(format!("Error applying transform: {}", transform.name()))?;
+
+        self.clipboard.copy(content.clone())?;
+        if self.store_transformed {
+            self.history
+                .store(content)
+                .context("Error storing history entry")?;
+        }
+
+        Ok(Next::Exit)
+    }
+
+    /// Newest text of the clipboard history, for the snippets' `{clipboard}` placeholder.
+    fn newest_text(&self) -> anyhow::Result<String> {
+        let history = self
+            .history
+            .list()
+            .context("Error listing clipboard history")?;
+        let Some(entry) = history
+            .iter()
+            .find(|e| matches!(e, ClipHistEntry::Text { .. }))
+        else {
+            bail!("No text in the clipboard history");
+        };
+        let content = self
+            .history
+            .decode(&RofiEntry::id(entry))
+            .context("Error getting history entry")?;
+
+        Ok(String::from_utf8_lossy(&content).into_owned())
+    }
+
+    /// Handle the action of a custom keyboard shortcut applied to the selected entries.
+    fn on_action(&mut self, action: Action, ids: &[usize]) -> anyhow::Result<Next> {
+        let current = match self.mode {
+            Mode::Text => &mut self.txt,
+            Mode::Image => &mut self.img,
+            Mode::Binary => &mut self.bin,
+            Mode::Pinned => &mut self.pin,
+            Mode::Snippets => {
+                self.snip.options.selected_row = ids[0];
+                return match (action, action.mode()) {
+                    (Action::Copy, _) => self.copy(ids),
+                    (_, Some(mode)) => {
+                        self.mode = mode;
+                        Ok(Next::Show)
+                    }
+                    _ => Ok(Next::Show),
+                };
+            }
+        };
+        current.options.selected_row = ids[0];
+
+        match action {
+            Action::Copy => return self.copy(ids),
+            Action::ImageMode => {
+                self.mode = Mode::Image;
+            }
+            Action::TextMode => {
+                self.mode = Mode::Text;
+            }
+            Action::BinaryMode => {
+                self.mode = Mode::Binary;
+            }
+            Action::PinnedMode => {
+                self.mode = Mode::Pinned;
+            }
+            Action::SnippetsMode => {
+                self.mode = Mode::Snippets;
+            }
+            Action::Transform => {
+                let id = Self::ids_of(&current.entries, ids[..1].iter().copied());
+                if let Some(id) = id.into_iter().next() {
+                    self.ask(Pending::Transform { id })?;
+                }
+            }
+            Action::ConvertColor => {
+                let id = Self::ids_of(&current.entries, ids[..1].iter().copied());
+                if let Some(id) = id.into_iter().next() {
+                    let content = self
+                        .backend(&id)
+                        .decode(&id)
+                        .context("Error getting history entry")?;
+                    match color::parse(&String::from_utf8_lossy(&content)) {
+                        Some(color) => self.ask(Pending::Color {
+                            conversions: color.conversions(),
+                        })?,
+                        None => warn!("Entry {id} isn't a color"),
+                    }
+                }
+            }
+            Action::Open => {
+                let Some(entry) = current.entries.get(ids[0]).cloned() else {
+                    bail!("Invalid id: {}", ids[0]);
+                };
+                let id = RofiEntry::id(&entry);
+                let content = self
+                    .backend(&id)
+                    .decode(&id)
+                    .context("Error getting history entry")?;
+                let target = match &entry {
+                    ClipHistEntry::Text { .. } => {
+                        Target::from_text(&String::from_utf8_lossy(&content))
+                    }
+                    ClipHistEntry::Image { content_type, .. } => {
+                        Some(Target::image(&id, content_type, &content)?)
+                    }
+                    ClipHistEntry::Binary { .. } => None,
+                };
+                match target {
+                    Some(target) => {
+                        self.opener.open(&target)?;
+                        return Ok(Next::Exit);
+                    }
+                    None => warn!("Entry {id} isn't a URL, an existing path or an image"),
+                }
+            }
+            Action::Dedupe => {
+                let copies = match self.dedupe {
+                    true => std::mem::take(&mut self.copies),
+                    false => {
+                        let entries = current.entries.clone();
+                        self.duplicates(&entries)
+                    }
+                };
+                let targets = copies.into_values().flatten().collect::<Vec<_>>();
+                debug!("Deleting {} older copies", targets.len());
+                self.delete(targets)?;
+            }
+            Action::Sort => {
+                self.sort = self.sort.next(self.mode);
+                self.apply_sort();
+                for state in [&mut self.txt, &mut self.img, &mut self.bin] {
+                    state.options.selected_row = 0;
+                }
+            }
+            Action::Delete => {
+                let targets = Self::ids_of(&current.entries, ids.iter().copied());
+                self.delete(targets)?;
+            }
+            Action::DeletePrevious | Action::DeleteNext => {
+                let rows = surrounding(&current.entries, &self.order, ids, action);
+                // the selected entry stays selected
+                current.options.selected_row -= rows.iter().filter(|row| **row < ids[0]).count();
+                let targets = Self::ids_of(&current.entries, rows.into_iter());
+                self.delete(targets)?;
+            }
+            Action::Pin => {
+                let targets = Self::ids_of(&current.entries, ids.iter().copied());
+                for id in targets.iter().filter(|id| !pinned::is_pinned(id)) {
+                    let content = self
+                        .history
+                        .decode(id)
+                        .context("Error getting history entry")?;
+                    self.pinned.store(content).context("Error pinning entry")?;
+                }
+                self.reload()?;
+            }
+            Action::Unpin => {
+                let targets = Self::ids_of(&current.entries, ids.iter().copied())
+                    .into_iter()
+                    .filter(|id| pinned::is_pinned(id))
+                    .collect::<Vec<_>>();
+                self.pinned
+                    .delete_many(&targets)
+                    .context("Error unpinning entries")?;
+                self.reload()?;
+            }
+            Action::MoveUp | Action::MoveDown => {
+                let up = action == Action::MoveUp;
+                let Some(id) = Self::ids_of(&current.entries, ids[..1].iter().copied()).pop()
+                else {
+                    return Ok(Next::Show);
+                };
+                if pinned::is_pinned(&id) {
+                    self.pinned.shift(&id, up).context("Error moving entry")?;
+                    // follow the moved entry
+                    current.options.selected_row = match up {
+                        true => ids[0].saturating_sub(1),
+                        false => ids[0] + 1,
+                    };
+                    self.reload()?;
+                }
+            }
+        }
+
+        Ok(Next::Show)
+    }
+
+    /// History ids of the entries at the given positions.
+    fn ids_of(entries: &[ClipHistEntry], positions: impl Iterator<Item = usize>) -> Vec<String> {
+        positions
+            .filter_map(|pos| entries.get(pos))
+            .map(RofiEntry::id)
+            .collect()
+    }
+
+    /// Older copies of the history's texts only differing by leading and trailing whitespace, by
+    /// the id of the newest one. Whitespace inside the texts matters, e.g. code indentation.
+    fn duplicates(&self, entries: &[ClipHistEntry]) -> HashMap<String, Vec<String>> {
+        let mut keys: HashMap<String, usize> = HashMap::new();
+        let mut groups: Vec<Vec<String>> = Vec::new();
+        for entry in entries {
+            let ClipHistEntry::Text { id, title, .. } = entry else {
+                continue;
+            };
+            if pinned::is_pinned(id) {
+                continue;
+            }
+            // previews collapse whitespace, so they only tell the candidates apart
+            match keys.get(title.trim()) {
+                Some(&group) => groups[group].push(id.clone()),
+                None => {
+                    keys.insert(title.trim().to_string(), groups.len());
+                    groups.push(vec![id.clone()]);
+                }
+            }
+        }
+
+        let mut copies = HashMap::new();
+        for group in groups.into_iter().filter(|group| group.len() > 1) {
+            // previews are cut off, so texts with the same one are compared in full
+            let mut confirmed: Vec<(String, Vec<String>)> = Vec::new();
+            for id in group {
+                let text = match self.history.decode(&id) {
+                    Ok(content) => String::from_utf8_lossy(&content).trim().to_string(),
+                    Err(e) => {
+                        warn!("Error getting history entry {id}: {e:?}");
+                        continue;
+                    }
+                };
+                match confirmed.iter_mut().find(|(t, _)| *t == text) {
+                    Some((_, ids)) => ids.push(id),
+                    None => confirmed.push((text, vec![id])),
+                }
+            }
+            for (_, mut ids) in confirmed.into_iter().filter(|(_, ids)| ids.len() > 1) {
+                let newest = ids.remove(0);
+                copies.insert(newest, ids);
+            }
+        }
+        debug!("Found {} duplicated texts", copies.len());
+
+        copies
+    }
+
+    /// Where an entry is stored, the history or the pinned entries.
+    fn backend(&self, id: &str) -> &dyn HistoryBackend {
+        if pinned::is_pinned(id) {
+            &self.pinned
+        } else {
+            self.history.as_ref()
+        }
+    }
+
+    /// Delete the entries in a single batch, then list the history again so the entries shown
+    /// match what's actually left. Pinned entries are kept, they have to be unpinned.
+    fn delete(&mut self, ids: Vec<String>) -> anyhow::Result<()> {
+        let mut ids = ids
+            .into_iter()
+            .filter(|id| !pinned::is_pinned(id))
+            .collect::<Vec<_>>();
+        // a grouped text goes away along with its copies
+        let copies = ids
+            .iter()
+            .filter_map(|id| self.copies.get(id))
+            .flatten()
+            .cloned()
+            .collect::<Vec<_>>();
+        ids.extend(copies);
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        let deleted = self
+            .history
+            .delete_many(&ids)
+            .context("Error deleting history entries")?;
+        if deleted.len() < ids.len() {
+            warn!(
+                "Only {} of {} entries were deleted",
+                deleted.len(),
+                ids.len()
+            );
+        }
+
+        self.reload()
+    }
+
+    /// List the history, splitting the entries by mode and keeping the selected rows in range.
+    ///
+    /// Pinned entries are listed in their own mode, and also at the top of the other ones. Secrets
+    /// are masked, the expired ones being deleted, and the cut off texts indexed.
+    fn reload(&mut self) -> anyhow::Result<()> {
+        trace!("Loading history entries");
+
+        let mut entries = self.pinned.list().context("Error listing pinned entries")?;
+        let mut history = self
+            .history
+            .list()
+            .context("Error listing clipboard history")?;
+        let pinned = self
+            .pinned
+            .copies(&entries, self.history.as_ref(), &history)
+            .context("Error reading pinned entries")?;
+        history.retain(|e| !pinned.contains(&RofiEntry::id(e)));
+        entries.extend(history);
+        if let Some(secrets) = &self.secrets {
+            let (history, pinned) = (self.history.as_ref(), &self.pinned);
+            let decode = |id: &str| match pinned::is_pinned(id) {
+                true => pinned.decode(id),
+                false => history.decode(id),
+            };
+            let expired = secrets
+                .mask(&mut entries, decode)
+                .context("Error masking secrets")?;
+            if !expired.is_empty() {
+                debug!("Deleting {} expired secrets", expired.len());
+                let deleted = self
+                    .history
+                    .delete_many(&expired)
+                    .context("Error deleting expired secrets")?;
+                entries.retain(|e| !deleted.contains(&RofiEntry::id(e)));
+            }
+        }
+        if let Some(index) = &mut self.index {
+            let (history, pinned, secrets) = (self.history.as_ref(), &self.pinned, &self.secrets);
+            let decode = |id: &str| match pinned::is_pinned(id) {
+                true => pinned.decode(id),
+                false => history.decode(id),
+            };
+            let is_secret = |text: &str| secrets.as_ref().is_some_and(|s| s.detect(text));
+            // best effort, the previews can still be searched
+            if let Err(e) = index.update(&entries, decode, is_secret) {
+                warn!("Error updating text index: {e:?}");
+            }
+        }
+        self.copies = match self.dedupe {
+            true => self.duplicates(&entries),
+            false => HashMap::new(),
+        };
+        let copies = self.copies.values().flatten().collect::<HashSet<_>>();
+        entries.retain(|e| !copies.contains(&RofiEntry::id(e)));
+
+        self.order.clear();
+        for state in [&mut self.txt, &mut self.img, &mut self.bin, &mut self.pin] {
+            state.entries.clear();
+        }
+        self.pin.entries = entries
+            .iter()
+            .filter(|e| pinned::is_pinned(&RofiEntry::id(*e)))
+            .cloned()
+            .collect();
+        for (pos, entry) in entries.into_iter().enumerate() {
+            self.order.insert(RofiEntry::id(&entry), pos);
+            match entry {
+                ClipHistEntry::Text { .. } => self.txt.entries.push(entry),
+                ClipHistEntry::Image { .. } => self.img.entries.push(entry),
+                ClipHistEntry::Binary { .. } => self.bin.entries.push(entry),
+            }
+        }
+        self.apply_sort();
+
+        for state in [&mut self.txt, &mut self.img, &mut self.bin, &mut self.pin] {
+            let last = state.entries.len().saturating_sub(1);
+            state.options.selected_row = state.options.selected_row.min(last);
+        }
+
+        Ok(())
+    }
+
+    /// Sort the image and file entries, showing the order in the prompt when not the default one.
+    fn apply_sort(&mut self) {
+        trace!("Sorting entries by {:?}", self.sort);
+
+        for (mode, state) in [(Mode::Image, &mut self.img), (Mode::Binary, &mut self.bin)] {
+            self.sort.apply(&mut state.entries, &self.order);
+            state.options.prompt = Some(match self.sort {
+                Sort::Newest => Self::title(mode),
+                sort => format!("{} ({})", Self::title(mode), sort.name()),
+            });
+            state.options.data = Some(format!("{}:{}", mode.name(), self.sort.name()));
+        }
+        self.txt.options.data = Some(format!("{}:{}", Mode::Text.name(), self.sort.name()));
+        self.pin.options.data = Some(format!("{}:{}", Mode::Pinned.name(), self.sort.name()));
+        self.snip.options.data = Some(format!("{}:{}", Mode::Snippets.name(), self.sort.name()));
+    }
+
     fn sync_cache(&self) -> anyhow::Result<usize> {
         trace!("Syncing cache");
 
-        let entries = self.cliphist.list().context("Error listing cliphist")?;
+        let mut entries = self
+            .history
+            .list()
+            .context("Error listing clipboard history")?;
+        entries.extend(self.pinned.list().context("Error listing pinned entries")?);
 
+        let swatches = entries
+            .iter()
+            .filter_map(|e| match e {
+                ClipHistEntry::Text {
+                    title,
+                    category: Category::Color,
+                    ..
+                } => color::parse(title),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let all = entries.clone();
         let entries = entries
             .iter()
             .filter(|e| matches!(e, ClipHistEntry::Image { .. }))
@@ -214,27 +1087,42 @@ impl ClipHistMode {
 
         let entries = entries.as_slice();
 
+        for swatch in &swatches {
+            if !self.cache.exists(&swatch.id()) {
+                self.cache.add(swatch, swatch.swatch());
+            }
+        }
         for entry in entries {
             if !self.cache.exists(&CacheEntry::id(*entry)) {
-                let id = match entry {
-                    ClipHistEntry::Text { id, .. } => id,
-                    ClipHistEntry::Image { id, .. } => id,
-                };
+                let id = RofiEntry::id(*entry);
                 let value = self
-                    .cliphist
-                    .value_of(id.into())
-                    .context("Error getting cliphist entry")?;
+                    .backend(&id)
+                    .decode(&id)
+                    .context("Error getting history entry")?;
                 self.cache.add(*entry, value);
             }
         }
 
-        let exclusions = entries
-            .iter()
-            .map(|e| *e as &dyn CacheEntry)
-            .map(|e| e.id())
-            .collect::<Vec<_>>();
+        self.cache
+            .prune(cache_ids(&all))
+            .context("Error syncing cache")
+    }
 
-        self.cache.prune(exclusions).context("Error syncing cache")
+    /// Custom shortcuts of the registry that apply to the given mode.
+    fn custom_kbs(registry: &[config::Keybinding], mode: Mode) -> Vec<KbCustom<Action>> {
+        registry
+            .iter()
+            .enumerate()
+            .filter(|(_, kb)| kb.action.applies_to(mode))
+            .map(|(idx, kb)| {
+                KbCustom::new(
+                    idx as i32 + 1,
+                    kb.shortcut.get_ref(),
+                    kb.description(),
+                    kb.action,
+                )
+            })
+            .collect()
     }
 
     fn theme(mode: Mode) -> Vec<String> {
@@ -242,6 +1130,11 @@ impl ClipHistMode {
 
         match mode {
         Mode::Text => vec![
+            "element { children: [element-icon, element-text]; orientation: horizontal; }".into(),
+            "element-icon { size: 1em; }".into(),
+            "listview { layout: vertical; }".into(),
+        ],
+        Mode::Snippets => vec![
             "element { children: [element-text]; orientation: vertical; }".into(),
             "listview { layout: vertical; }".into(),
         ],
@@ -250,6 +1143,11 @@ impl ClipHistMode {
             "element-icon { size: 228px; padding: 0px; }".into(),
             "listview { layout: vertical; lines: 3; columns: 3; fixed-height: true; fixed-columns: true; }".into(),
         ],
+        Mode::Binary | Mode::Pinned => vec![
+            "element { children: [element-icon, element-text]; orientation: horizontal; }".into(),
+            "element-icon { size: 32px; }".into(),
+            "listview { layout: vertical; }".into(),
+        ],
     }
     }
 
@@ -257,6 +1155,88 @@ impl ClipHistMode {
         match mode {
             Mode::Text => "Texts".into(),
             Mode::Image => "Images".into(),
+            Mode::Binary => "Files".into(),
+            Mode::Pinned => "Pinned".into(),
+            Mode::Snippets => "Snippets".into(),
         }
     }
 }
+
+/// Files of the entries in the thumbnails cache: the images and the color swatches.
+pub fn cache_ids(entries: &[ClipHistEntry]) -> Vec<String> {
+    entries
+        .iter()
+        .filter_map(|e| match e {
+            ClipHistEntry::Image { .. } => Some(CacheEntry::id(e)),
+            ClipHistEntry::Text {
+                title,
+                category: Category::Color,
+                ..
+            } => color::parse(title).map(|color| color.id()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classify::Category;
+
+    fn text(id: &str) -> ClipHistEntry {
+        ClipHistEntry::Text {
+            id: id.into(),
+            title: id.into(),
+            category: Category::Text,
+        }
+    }
+
+    /// Entries listed as the text mode does, pinned ones first, in history order.
+    fn listed(ids: &[&str]) -> (Vec<ClipHistEntry>, HashMap<String, usize>) {
+        let entries = ids.iter().map(|id| text(id)).collect::<Vec<_>>();
+        let order = ids
+            .iter()
+            .enumerate()
+            .map(|(pos, id)| (id.to_string(), pos))
+            .collect();
+
+        (entries, order)
+    }
+
+    #[test]
+    fn finds_the_entries_around_the_selection() {
+        let (entries, order) = listed(&["5", "4", "3", "2", "1"]);
+
+        let rows = surrounding(&entries, &order, &[2], Action::DeletePrevious);
+        assert_eq!(rows, vec![0, 1]);
+        let rows = surrounding(&entries, &order, &[2], Action::DeleteNext);
+        assert_eq!(rows, vec![3, 4]);
+        // several selected, around all of them
+        let rows = surrounding(&entries, &order, &[3, 1], Action::DeletePrevious);
+        assert_eq!(rows, vec![0]);
+        let rows = surrounding(&entries, &order, &[3, 1], Action::DeleteNext);
+        assert_eq!(rows, vec![4]);
+    }
+
+    #[test]
+    fn follows_the_history_order_whatever_the_sort() {
+        let (mut entries, order) = listed(&["3", "2", "1"]);
+        entries.reverse();
+
+        let rows = surrounding(&entries, &order, &[0], Action::DeletePrevious);
+        assert_eq!(rows, vec![1, 2]);
+        let rows = surrounding(&entries, &order, &[0], Action::DeleteNext);
+        assert!(rows.is_empty());
+    }
+
+    #[test]
+    fn leaves_the_pinned_entries_out() {
+        let (entries, order) = listed(&["pin-1", "pin-2", "3", "2", "1"]);
+
+        // only the history entry before the selected one, so the selection moves up by one row
+        let rows = surrounding(&entries, &order, &[3], Action::DeletePrevious);
+        assert_eq!(rows, vec![2]);
+        let rows = surrounding(&entries, &order, &[0], Action::DeleteNext);
+        assert_eq!(rows, vec![2, 3, 4]);
+    }
+}
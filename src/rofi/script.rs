@@ -0,0 +1,292 @@
+use std::{
+    env,
+    io::{self, Write},
+};
+
+use anyhow::Context;
+use log::{debug, trace};
+
+use crate::cache;
+
+use super::{kb_help, row, RofiEntry, RofiOptions, RofiResult};
+
+/// Env var rofi uses to tell the script why it was called.
+pub const ROFI_RETV: &str = "ROFI_RETV";
+/// Env var holding the `info` row option of the selected entry.
+const ROFI_INFO: &str = "ROFI_INFO";
+/// Env var holding the `data` mode option printed by the previous callback.
+const ROFI_DATA: &str = "ROFI_DATA";
+
+/// Rofi script mode backend (see `rofi-script(5)`).
+///
+/// Rofi runs the script once per user interaction, so every process handles at most one
+/// callback: the one rofi describes through `ROFI_RETV`, `ROFI_INFO` and `ROFI_DATA`.
+///
+/// Scripts can't bind keys, rofi's own `kb-custom-N` bindings trigger the actions. The help message
+/// still lists the configured shortcuts, so they're only right when the user's `kb-custom-N`
+/// bindings match them, in the order of the configured keybindings.
+pub struct Script {
+    callback: Option<Callback>,
+    data: Option<String>,
+}
+
+/// What the user did before rofi called the script.
+struct Callback {
+    retv: i32,
+    info: Option<String>,
+    input: Option<String>,
+}
+
+impl Script {
+    /// Read the callback rofi describes through the environment, `input` being the script's
+    /// argument.
+    pub fn from_env(input: Option<String>) -> anyhow::Result<Self> {
+        let retv = env::var(ROFI_RETV)
+            .context("Not running as a rofi script")?
+            .parse::<i32>()
+            .context("parsing ROFI_RETV")?;
+        let info = env::var(ROFI_INFO).ok().filter(|i| !i.is_empty());
+        let data = env::var(ROFI_DATA).ok().filter(|d| !d.is_empty());
+
+        debug!("Rofi script callback: retv={retv}, info={info:?}, data={data:?}");
+
+        Ok(Self {
+            callback: (retv != 0).then_some(Callback { retv, info, input }),
+            data,
+        })
+    }
+
+    /// Data saved by the previous callback.
+    pub fn data(&self) -> Option<&str> {
+        self.data.as_deref()
+    }
+
+    /// Return the pending callback, or print the entries for rofi when there is none left.
+    pub fn run<A: Copy>(
+        &mut self,
+        entries: &[&dyn RofiEntry],
+        options: &RofiOptions<A>,
+        cache: &cache::SimpleCache,
+    ) -> anyhow::Result<RofiResult<A>> {
+        if let Some(callback) = self.callback.take() {
+            match callback.result(entries, options)? {
+                Some(result) => return Ok(result),
+                // the list changed since it was shown, it's shown again instead
+                None => debug!("Selected entry is gone, listing the entries again"),
+            }
+        }
+
+        let mut stdout = io::stdout().lock();
+        stdout
+            .write_all(&mode_options(entries, options))
+            .context("writing mode options to rofi")?;
+        for (idx, entry) in entries.iter().enumerate() {
+            // rofi hands the info back, use it to carry the index and id along with the entry's
+            // own info, the next callback listing the entries anew
+            let info = match entry.info() {
+                Some(info) => format!("{idx}\t{}\t{info}", entry.id()),
+                None => format!("{idx}\t{}", entry.id()),
+            };
+            let mut extra = vec![("info", info)];
+            if entry.urgent() {
+                extra.push(("urgent", "true".into()));
+            }
+            if entry.active() {
+                extra.push(("active", "true".into()));
+            }
+            stdout
+                .write_all(&row(*entry, cache, &extra)?)
+                .context("writing entries to rofi through stdout")?;
+        }
+        stdout.flush().context("flushing rofi's output")?;
+
+        Ok(RofiResult::Empty)
+    }
+}
+
+impl Callback {
+    /// Result of the callback, `None` when the selected entry is no longer listed.
+    fn result<A: Copy>(
+        self,
+        entries: &[&dyn RofiEntry],
+        options: &RofiOptions<A>,
+    ) -> anyhow::Result<Option<RofiResult<A>>> {
+        let id = match self.info {
+            Some(info) => {
+                let mut fields = info.splitn(3, '\t');
+                let idx = fields
+                    .next()
+                    .unwrap_or_default()
+                    .parse::<usize>()
+                    .context("parsing ROFI_INFO")?;
+                let id = fields.next().context("No entry id in ROFI_INFO")?;
+                // entries may have been pruned or expired since they were listed
+                let position = match entries.get(idx) {
+                    Some(entry) if entry.id() == id => Some(idx),
+                    _ => entries.iter().position(|entry| entry.id() == id),
+                };
+                match position {
+                    Some(position) => Some(position),
+                    None => return Ok(None),
+                }
+            }
+            None => None,
+        };
+
+        let result = match (self.retv, id, options.action(self.retv)) {
+            (1, Some(id), _) => RofiResult::Selection { id },
+            (2, _, _) => RofiResult::Custom(self.input.unwrap_or_default()),
+            (_, Some(id), Some(action)) => RofiResult::Action { action, id },
+            (retv, _, _) => {
+                trace!("Unhandled rofi callback: {retv}");
+                RofiResult::Cancel
+            }
+        };
+
+        Ok(Some(result))
+    }
+}
+
+/// Mode options (`\0option\x1fvalue` lines) configuring rofi for the next round.
+fn mode_options<A>(entries: &[&dyn RofiEntry], options: &RofiOptions<A>) -> Vec<u8> {
+    let mut mode = vec![
+        ("keep-selection", "true".to_string()),
+        ("new-selection", options.selected_row.to_string()),
+    ];
+    if let Some(prompt) = &options.prompt {
+        mode.push(("prompt", prompt.clone()));
+    }
+    if entries.is_empty() && options.no_custom {
+        mode.push(("message", "No clipboard entries to show".into()));
+    } else if let Some(help) = kb_help(&options.custom_kbs) {
+        // the configured shortcuts, see the note on `Script` about rofi's bindings
+        mode.push(("message", help));
+    }
+    if !options.custom_kbs.is_empty() {
+        mode.push(("use-hot-keys", "true".into()));
+    }
+    if options.no_custom {
+        mode.push(("no-custom", "true".into()));
+    }
+    if let Some(data) = &options.data {
+        mode.push(("data", data.clone()));
+    }
+    if !options.theme_str.is_empty() {
+        mode.push(("theme", options.theme_str.join(" ")));
+    }
+
+    mode.iter()
+        .flat_map(|(key, value)| format!("\0{key}\x1f{value}\n").into_bytes())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rofi::KbCustom;
+
+    struct Entry(&'static str);
+
+    impl RofiEntry for Entry {
+        fn id(&self) -> String {
+            self.0.into()
+        }
+        fn label(&self) -> String {
+            self.0.into()
+        }
+        fn icon(&self) -> Option<String> {
+            None
+        }
+        fn meta(&self) -> Option<String> {
+            None
+        }
+    }
+
+    /// Result of a callback over the entries `a`, `b` and `c`, `kb-custom-2` being bound to `'d'`.
+    fn result(retv: i32, info: Option<&str>, input: Option<&str>) -> Option<RofiResult<char>> {
+        let entries = [Entry("a"), Entry("b"), Entry("c")];
+        let entries = entries
+            .iter()
+            .map(|e| e as &dyn RofiEntry)
+            .collect::<Vec<_>>();
+        let options = RofiOptions::new("p", "", [KbCustom::new(2, "Alt+d", "delete", 'd')], [""]);
+        let callback = Callback {
+            retv,
+            info: info.map(Into::into),
+            input: input.map(Into::into),
+        };
+
+        callback.result(&entries, &options).unwrap()
+    }
+
+    #[test]
+    fn selects_the_listed_entry() {
+        assert!(matches!(
+            result(1, Some("1\tb"), None),
+            Some(RofiResult::Selection { id: 1 })
+        ));
+        // the entry's own info follows the id
+        assert!(matches!(
+            result(1, Some("2\tc\tinfo\twith tabs"), None),
+            Some(RofiResult::Selection { id: 2 })
+        ));
+    }
+
+    #[test]
+    fn finds_entries_that_moved() {
+        assert!(matches!(
+            result(1, Some("0\tc"), None),
+            Some(RofiResult::Selection { id: 2 })
+        ));
+        assert!(matches!(
+            result(1, Some("7\tb"), None),
+            Some(RofiResult::Selection { id: 1 })
+        ));
+    }
+
+    #[test]
+    fn skips_entries_that_are_gone() {
+        assert!(result(1, Some("1\tx"), None).is_none());
+        assert!(result(11, Some("1\tx"), None).is_none());
+    }
+
+    #[test]
+    fn returns_the_custom_input() {
+        assert!(matches!(
+            result(2, None, Some("typed")),
+            Some(RofiResult::Custom(input)) if input == "typed"
+        ));
+        assert!(matches!(
+            result(2, None, None),
+            Some(RofiResult::Custom(input)) if input.is_empty()
+        ));
+    }
+
+    #[test]
+    fn maps_custom_keys_to_their_actions() {
+        // kb-custom-2 exits with 11
+        assert!(matches!(
+            result(11, Some("0\ta"), None),
+            Some(RofiResult::Action { action: 'd', id: 0 })
+        ));
+        assert!(matches!(
+            result(10, Some("0\ta"), None),
+            Some(RofiResult::Cancel)
+        ));
+        assert!(matches!(result(11, None, None), Some(RofiResult::Cancel)));
+    }
+
+    #[test]
+    fn rejects_invalid_info() {
+        let entries: [&dyn RofiEntry; 0] = [];
+        let callback = |info: &str| Callback {
+            retv: 1,
+            info: Some(info.into()),
+            input: None,
+        };
+        let options = RofiOptions::<char>::default();
+
+        assert!(callback("x\ta").result(&entries, &options).is_err());
+        assert!(callback("0").result(&entries, &options).is_err());
+    }
+}
@@ -0,0 +1,286 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use chrono::format::{Item, StrftimeItems};
+use directories_next::BaseDirs;
+use log::{debug, trace, warn};
+use serde::{Deserialize, Serialize};
+
+/// Format of the `{date}` placeholder when none is given.
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Reusable text (e.g. a signature or a shell one-liner), its placeholders being expanded when
+/// copied:
+///
+/// - `{date}` or `{date:FORMAT}`: the current date, using `strftime` like formats
+/// - `{env:NAME}`: the value of an environment variable
+/// - `{clipboard}`: the newest text of the clipboard history
+/// - `{prompt:NAME}`: a value asked to the user, once per name
+///
+/// Anything else between braces is left as is.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snippet {
+    pub name: String,
+    pub text: String,
+}
+
+/// A piece of a snippet's text.
+enum Token<'a> {
+    Text(&'a str),
+    Date(&'a str),
+    Env(&'a str),
+    Clipboard,
+    Prompt(&'a str),
+}
+
+/// Default directory of the snippets, `$XDG_CONFIG_HOME/rofi-cliphist/snippets`.
+pub fn default_dir() -> anyhow::Result<PathBuf> {
+    let dirs = BaseDirs::new().context("Error getting base directories")?;
+
+    Ok(dirs.config_dir().join("rofi-cliphist").join("snippets"))
+}
+
+/// Load the snippets of a directory, one per file named after it, sorted by name.
+pub fn load_dir(dir: &Path) -> anyhow::Result<Vec<Snippet>> {
+    trace!("Loading snippets from {dir:?}");
+
+    let mut snippets = Vec::new();
+    for entry in fs::read_dir(dir).context(format!("Error reading {dir:?}"))? {
+        let path = entry.context(format!("Error reading {dir:?}"))?.path();
+        let Some(name) = path.file_stem().map(|n| n.to_string_lossy().to_string()) else {
+            continue;
+        };
+        if name.starts_with('.') || !path.is_file() {
+            continue;
+        }
+
+        let Ok(text) = fs::read_to_string(&path) else {
+            warn!("Ignoring snippet {path:?}, it isn't text");
+            continue;
+        };
+        // editors end files with a new line, which is rarely wanted when pasting
+        let text = text.strip_suffix('\n').unwrap_or(&text).to_string();
+
+        snippets.push(Snippet { name, text });
+    }
+    snippets.sort_by(|a, b| a.name.cmp(&b.name));
+
+    debug!("Found {} snippets in {dir:?}", snippets.len());
+
+    Ok(snippets)
+}
+
+impl Snippet {
+    /// Names of the values asked to the user, in order of appearance and without duplicates.
+    pub fn variables(&self) -> Vec<&str> {
+        let mut variables = Vec::new();
+        for token in parse(&self.text) {
+            if let Token::Prompt(name) = token {
+                if !variables.contains(&name) {
+                    variables.push(name);
+                }
+            }
+        }
+
+        variables
+    }
+
+    /// Text with the placeholders replaced, `values` being the ones of [`Snippet::variables`]
+    /// and `clipboard` giving the newest history text, only called when needed.
+    pub fn expand(
+        &self,
+        values: &[String],
+        clipboard: impl FnOnce() -> anyhow::Result<String>,
+    ) -> anyhow::Result<String> {
+        let variables = self.variables();
+        if values.len() != variables.len() {
+            bail!(
+                "Snippet {} expects {} values, got {}",
+                self.name,
+                variables.len(),
+                values.len()
+            );
+        }
+
+        let now = chrono::Local::now();
+        let mut clipboard = Some(clipboard);
+        let mut newest = String::new();
+
+        let mut text = String::with_capacity(self.text.len());
+        for token in parse(&self.text) {
+            match token {
+                Token::Text(t) => text.push_str(t),
+                Token::Date(format) => {
+                    let items = StrftimeItems::new(format).collect::<Vec<_>>();
+                    if items.contains(&Item::Error) {
+                        bail!("Invalid date format in snippet {}: {format}", self.name);
+                    }
+                    text.push_str(&now.format_with_items(items.iter()).to_string());
+                }
+                Token::Env(name) => match env::var(name) {
+                    Ok(value) => text.push_str(&value),
+                    Err(_) => warn!("Environment variable {name} not set, expanding to nothing"),
+                },
+                Token::Clipboard => {
+                    if let Some(clipboard) = clipboard.take() {
+                        newest = clipboard().context("Error getting the newest history text")?;
+                    }
+                    text.push_str(&newest);
+                }
+                Token::Prompt(name) => {
+                    let pos = variables.iter().position(|v| *v == name).unwrap_or(0);
+                    text.push_str(&values[pos]);
+                }
+            }
+        }
+
+        Ok(text)
+    }
+}
+
+/// Split a snippet's text into the literal parts and the placeholders.
+fn parse(text: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start + 1..].find(['{', '}']) else {
+            break;
+        };
+        let end = start + 1 + len;
+
+        let placeholder = match &rest[start + 1..end] {
+            "clipboard" => Some(Token::Clipboard),
+            "date" => Some(Token::Date(DATE_FORMAT)),
+            inner => match inner.split_once(':') {
+                Some(("date", format)) => Some(Token::Date(format)),
+                Some(("env", name)) => Some(Token::Env(name)),
+                Some(("prompt", name)) => Some(Token::Prompt(name)),
+                _ => None,
+            },
+        };
+
+        match placeholder {
+            // a nested brace or unknown placeholder, e.g. shell's `${VAR}`, is kept as is
+            _ if rest.as_bytes()[end] == b'{' => {
+                tokens.push(Token::Text(&rest[..end]));
+                rest = &rest[end..];
+            }
+            None => {
+                tokens.push(Token::Text(&rest[..=end]));
+                rest = &rest[end + 1..];
+            }
+            Some(placeholder) => {
+                tokens.push(Token::Text(&rest[..start]));
+                tokens.push(placeholder);
+                rest = &rest[end + 1..];
+            }
+        }
+    }
+    tokens.push(Token::Text(rest));
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    fn snippet(text: &str) -> Snippet {
+        Snippet {
+            name: "test".into(),
+            text: text.into(),
+        }
+    }
+
+    fn expand(text: &str, values: &[&str]) -> String {
+        let values = values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        snippet(text)
+            .expand(&values, || Ok("copied".into()))
+            .unwrap()
+    }
+
+    #[test]
+    fn expands_placeholders() {
+        env::set_var("ROTO_SNIPPET_TEST", "value");
+        let year = chrono::Local::now().format("%Y").to_string();
+        let today = chrono::Local::now().format(DATE_FORMAT).to_string();
+
+        assert_eq!(expand("env: {env:ROTO_SNIPPET_TEST}", &[]), "env: value");
+        assert_eq!(expand("{env:ROTO_SNIPPET_UNSET}.", &[]), ".");
+        assert_eq!(expand("{date}", &[]), today);
+        assert_eq!(expand("(c) {date:%Y}", &[]), format!("(c) {year}"));
+        assert_eq!(expand("[{clipboard}]", &[]), "[copied]");
+        assert_eq!(expand("hi {prompt:name}!", &["bob"]), "hi bob!");
+        assert_eq!(expand("no placeholders", &[]), "no placeholders");
+    }
+
+    #[test]
+    fn asks_each_variable_once() {
+        let snippet = snippet("{prompt:a} {prompt:b} {prompt:a} {prompt:}");
+
+        assert_eq!(snippet.variables(), vec!["a", "b", ""]);
+        assert_eq!(
+            expand(&snippet.text, &["1", "2", "3"]),
+            "1 2 1 3".to_string()
+        );
+        let e = snippet.expand(&[], || Ok(String::new())).unwrap_err();
+        assert_eq!(e.to_string(), "Snippet test expects 3 values, got 0");
+    }
+
+    #[test]
+    fn reads_the_clipboard_once_and_only_when_needed() {
+        let calls = Cell::new(0);
+        let clipboard = || {
+            calls.set(calls.get() + 1);
+            Ok("copied".to_string())
+        };
+
+        let text = snippet("{clipboard} {clipboard}")
+            .expand(&[], clipboard)
+            .unwrap();
+        assert_eq!(text, "copied copied");
+        assert_eq!(calls.get(), 1);
+
+        snippet("{date}").expand(&[], clipboard).unwrap();
+        assert_eq!(calls.get(), 1);
+
+        let e = snippet("{clipboard}")
+            .expand(&[], || anyhow::bail!("empty history"))
+            .unwrap_err();
+        assert_eq!(e.to_string(), "Error getting the newest history text");
+    }
+
+    #[test]
+    fn keeps_unknown_placeholders() {
+        assert_eq!(expand("echo ${HOME}", &[]), "echo ${HOME}");
+        assert_eq!(
+            expand("{name} {env} {foo:bar}", &[]),
+            "{name} {env} {foo:bar}"
+        );
+        assert_eq!(expand(r#"{"a": {"b": 1}}"#, &[]), r#"{"a": {"b": 1}}"#);
+        assert_eq!(expand("{}", &[]), "{}");
+    }
+
+    #[test]
+    fn keeps_unbalanced_braces() {
+        assert_eq!(expand("{date", &[]), "{date");
+        assert_eq!(expand("}{clipboard", &[]), "}{clipboard");
+        assert_eq!(expand("a } b", &[]), "a } b");
+        assert_eq!(expand("{{clipboard}}", &[]), "{copied}");
+        assert_eq!(expand("{ {clipboard}", &[]), "{ copied");
+        assert_eq!(expand("{clipboard}{", &[]), "copied{");
+    }
+
+    #[test]
+    fn rejects_invalid_date_formats() {
+        let e = snippet("{date:%Q}")
+            .expand(&[], || Ok(String::new()))
+            .unwrap_err();
+        assert_eq!(e.to_string(), "Invalid date format in snippet test: %Q");
+    }
+}
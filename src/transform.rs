@@ -0,0 +1,310 @@
+use std::{
+    io::{self, Write},
+    process::{Command, Stdio},
+};
+
+use anyhow::{bail, Context};
+use base64::{
+    alphabet,
+    engine::{general_purpose, DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
+};
+use log::{debug, trace};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+
+/// Characters kept as is when URL encoding, the unreserved ones of RFC 3986.
+const URL_COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Transformations done in-process.
+#[derive(Debug, Clone, Copy)]
+pub enum Builtin {
+    Trim,
+    Lowercase,
+    Uppercase,
+    UrlEncode,
+    UrlDecode,
+    Base64Encode,
+    Base64Decode,
+    JsonPretty,
+    JsonMinify,
+    ShellQuote,
+}
+
+/// External command the entry is piped through, e.g. `sort -u`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Filter {
+    pub name: String,
+    /// Run by `sh -c`, reading the entry from stdin and writing the result to stdout
+    pub command: String,
+}
+
+/// A transformation applied to an entry before copying it.
+#[derive(Debug, Clone)]
+pub enum Transform {
+    Builtin(Builtin),
+    Filter(Filter),
+}
+
+/// The built-in transforms followed by the user defined filters.
+pub fn all(filters: Vec<Filter>) -> Vec<Transform> {
+    [
+        Builtin::Trim,
+        Builtin::Lowercase,
+        Builtin::Uppercase,
+        Builtin::UrlEncode,
+        Builtin::UrlDecode,
+        Builtin::Base64Encode,
+        Builtin::Base64Decode,
+        Builtin::JsonPretty,
+        Builtin::JsonMinify,
+        Builtin::ShellQuote,
+    ]
+    .into_iter()
+    .map(Transform::Builtin)
+    .chain(filters.into_iter().map(Transform::Filter))
+    .collect()
+}
+
+impl Builtin {
+    fn name(&self) -> &'static str {
+        match self {
+            Builtin::Trim => "Trim whitespace",
+            Builtin::Lowercase => "Lowercase",
+            Builtin::Uppercase => "Uppercase",
+            Builtin::UrlEncode => "URL encode",
+            Builtin::UrlDecode => "URL decode",
+            Builtin::Base64Encode => "Base64 encode",
+            Builtin::Base64Decode => "Base64 decode",
+            Builtin::JsonPretty => "Pretty-print JSON",
+            Builtin::JsonMinify => "Minify JSON",
+            Builtin::ShellQuote => "Shell quote",
+        }
+    }
+
+    fn apply(&self, content: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        let result = match self {
+            Builtin::Trim => text(content)?.trim().to_string(),
+            Builtin::Lowercase => text(content)?.to_lowercase(),
+            Builtin::Uppercase => text(content)?.to_uppercase(),
+            Builtin::UrlEncode => utf8_percent_encode(&text(content)?, URL_COMPONENT).to_string(),
+            Builtin::UrlDecode => return Ok(percent_decode_str(&text(content)?).collect()),
+            Builtin::Base64Encode => general_purpose::STANDARD.encode(content),
+            Builtin::Base64Decode => return base64_decode(&text(content)?),
+            Builtin::JsonPretty => serde_json::to_string_pretty(&parse_json(&text(content)?)?)?,
+            Builtin::JsonMinify => serde_json::to_string(&parse_json(&text(content)?)?)?,
+            Builtin::ShellQuote => shell_quote(&text(content)?),
+        };
+
+        Ok(result.into_bytes())
+    }
+}
+
+impl Transform {
+    /// Name shown in the transforms menu.
+    pub fn name(&self) -> &str {
+        match self {
+            Transform::Builtin(builtin) => builtin.name(),
+            Transform::Filter(filter) => &filter.name,
+        }
+    }
+
+    /// Transform the content of an entry.
+    pub fn apply(&self, content: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        trace!("Applying transform: {}", self.name());
+        match self {
+            Transform::Builtin(builtin) => builtin.apply(content),
+            Transform::Filter(filter) => filter.apply(content),
+        }
+    }
+}
+
+impl Filter {
+    fn apply(&self, content: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        debug!("Executing filter: {}", self.command);
+        let mut child = Command::new("sh")
+            .args(["-c", &self.command])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context(format!("Error executing {:?}", self.command))?;
+
+        // written from another thread, the filter may fill its stdout before reading everything,
+        // or not read it at all
+        let mut stdin = child.stdin.take().context("Failed to open stdin")?;
+        let writer = std::thread::spawn(move || match stdin.write_all(&content) {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+            result => result,
+        });
+
+        let output = child
+            .wait_with_output()
+            .context(format!("Error executing {:?}", self.command))?;
+        writer
+            .join()
+            .map_err(|_| anyhow::anyhow!("Error writing to {:?}", self.command))?
+            .context(format!("Error writing to {:?}", self.command))?;
+
+        if !output.status.success() {
+            bail!(
+                "{:?} exited with {}: {}",
+                self.command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        Ok(output.stdout)
+    }
+}
+
+/// Decode base64, either the standard or the URL safe alphabet, padded or not.
+fn base64_decode(text: &str) -> anyhow::Result<Vec<u8>> {
+    let text = text
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>();
+    let alphabet = match text.contains(['-', '_']) {
+        true => &alphabet::URL_SAFE,
+        false => &alphabet::STANDARD,
+    };
+    let engine = GeneralPurpose::new(
+        alphabet,
+        GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+    );
+
+    engine.decode(text).context("Invalid base64")
+}
+
+/// Quote a text to be used as a single shell word.
+pub fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+fn text(content: Vec<u8>) -> anyhow::Result<String> {
+    String::from_utf8(content).context("Entry isn't text")
+}
+
+fn parse_json(text: &str) -> anyhow::Result<serde_json::Value> {
+    serde_json::from_str(text).context("Invalid JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builtin(builtin: Builtin, text: &str) -> anyhow::Result<String> {
+        let result = Transform::Builtin(builtin).apply(text.as_bytes().to_vec())?;
+        Ok(String::from_utf8(result).unwrap())
+    }
+
+    fn filter(command: &str) -> Transform {
+        Transform::Filter(Filter {
+            name: "test".into(),
+            command: command.into(),
+        })
+    }
+
+    #[test]
+    fn changes_the_case_and_whitespace() {
+        assert_eq!(builtin(Builtin::Trim, " \n a b \t").unwrap(), "a b");
+        assert_eq!(builtin(Builtin::Lowercase, "ÀbC").unwrap(), "àbc");
+        assert_eq!(builtin(Builtin::Uppercase, "àbc").unwrap(), "ÀBC");
+    }
+
+    #[test]
+    fn encodes_urls() {
+        assert_eq!(
+            builtin(Builtin::UrlEncode, "a b/ñ?x=1&y-_.~").unwrap(),
+            "a%20b%2F%C3%B1%3Fx%3D1%26y-_.~"
+        );
+        assert_eq!(
+            builtin(Builtin::UrlDecode, "a%20b%2F%C3%B1%3fx").unwrap(),
+            "a b/ñ?x"
+        );
+        assert_eq!(builtin(Builtin::UrlDecode, "100%").unwrap(), "100%");
+    }
+
+    #[test]
+    fn encodes_base64() {
+        assert_eq!(builtin(Builtin::Base64Encode, "hi?>").unwrap(), "aGk/Pg==");
+        assert_eq!(builtin(Builtin::Base64Decode, "aGk/Pg==").unwrap(), "hi?>");
+        // unpadded, URL safe and wrapped
+        assert_eq!(builtin(Builtin::Base64Decode, "aGk_Pg").unwrap(), "hi?>");
+        assert_eq!(
+            builtin(Builtin::Base64Decode, "aGk/\nPg==\n").unwrap(),
+            "hi?>"
+        );
+        assert!(builtin(Builtin::Base64Decode, "not base64!").is_err());
+    }
+
+    #[test]
+    fn formats_json() {
+        let json = r#"{ "b": [1, 2], "a": null }"#;
+
+        assert_eq!(
+            builtin(Builtin::JsonPretty, json).unwrap(),
+            "{\n  \"b\": [\n    1,\n    2\n  ],\n  \"a\": null\n}"
+        );
+        assert_eq!(
+            builtin(Builtin::JsonMinify, json).unwrap(),
+            r#"{"b":[1,2],"a":null}"#
+        );
+        assert!(builtin(Builtin::JsonMinify, "{").is_err());
+    }
+
+    #[test]
+    fn quotes_for_the_shell() {
+        assert_eq!(builtin(Builtin::ShellQuote, "it's").unwrap(), r"'it'\''s'");
+        assert_eq!(builtin(Builtin::ShellQuote, "").unwrap(), "''");
+    }
+
+    #[test]
+    fn rejects_binaries_in_text_transforms() {
+        let content = vec![0xff, 0xfe];
+
+        let e = Transform::Builtin(Builtin::Uppercase)
+            .apply(content.clone())
+            .unwrap_err();
+        assert_eq!(e.to_string(), "Entry isn't text");
+        assert_eq!(
+            Transform::Builtin(Builtin::Base64Encode)
+                .apply(content)
+                .unwrap(),
+            b"//4="
+        );
+    }
+
+    #[test]
+    fn pipes_through_filters() {
+        let output = filter("tr a-z A-Z").apply(b"hello".to_vec()).unwrap();
+        assert_eq!(output, b"HELLO");
+
+        // bigger than a pipe's buffer, without the filter reading all of it first
+        let content = vec![b'x'; 1024 * 1024];
+        let output = filter("cat").apply(content.clone()).unwrap();
+        assert_eq!(output, content);
+        let output = filter("echo done").apply(content).unwrap();
+        assert_eq!(output, b"done\n");
+
+        // not necessarily text
+        let output = filter("printf '\\377'").apply(vec![]).unwrap();
+        assert_eq!(output, vec![0xff]);
+    }
+
+    #[test]
+    fn fails_when_filters_fail() {
+        let e = filter("echo oops >&2; exit 3")
+            .apply(b"hello".to_vec())
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            r#""echo oops >&2; exit 3" exited with exit status: 3: oops"#
+        );
+    }
+}
@@ -0,0 +1,117 @@
+#!/usr/bin/env python3
+"""Generate the cliphist database fixtures used by the bbolt reader's tests.
+
+Writes the files the way bbolt lays them out on disk (two meta pages, a freelist page and the
+bucket pages), using 1 KiB pages to keep them small:
+
+- cliphist-inline.db: a small history, the "b" bucket being inlined in its parent's value
+- cliphist-tree.db: a "b" bucket rooted at a branch page with two leaves, the second one
+  holding an overflow value, along with an inline "a" bucket and a plain "c" value
+"""
+
+import struct
+import zlib
+from pathlib import Path
+
+PAGE_SIZE = 1024
+BRANCH, LEAF, META, FREELIST = 0x01, 0x02, 0x04, 0x10
+BUCKET_LEAF = 0x01
+
+
+def header(pgid, flags, count, overflow=0):
+    return struct.pack("<QHHI", pgid, flags, count, overflow)
+
+
+def fnv1a(data):
+    h = 0xCBF29CE484222325
+    for b in data:
+        h = ((h ^ b) * 0x100000001B3) & 0xFFFFFFFFFFFFFFFF
+    return h
+
+
+def meta(pgid, root, txid, high_water):
+    body = struct.pack("<IIII", 0xED0CDAED, 2, PAGE_SIZE, 0)
+    body += struct.pack("<QQQQQ", root, 0, 2, high_water, txid)
+    return header(pgid, META, 0) + body + struct.pack("<Q", fnv1a(body))
+
+
+def leaf(pgid, elements):
+    """elements: (flags, key, value) sorted by key."""
+    data = b""
+    out = b""
+    for idx, (flags, key, value) in enumerate(elements):
+        pos = len(elements) * 16 + len(data) - idx * 16
+        out += struct.pack("<IIII", flags, pos, len(key), len(value))
+        data += key + value
+    body = out + data
+    overflow = max(0, -(-(16 + len(body)) // PAGE_SIZE) - 1)
+    return header(pgid, LEAF, len(elements), overflow) + body
+
+
+def branch(pgid, children):
+    """children: (first key, page id) sorted by key."""
+    data = b""
+    out = b""
+    for idx, (key, child) in enumerate(children):
+        pos = len(children) * 16 + len(data) - idx * 16
+        out += struct.pack("<IIQ", pos, len(key), child)
+        data += key
+    return header(pgid, BRANCH, len(children)) + out + data
+
+
+def bucket(root, inline=b""):
+    return struct.pack("<QQ", root, 0) + inline
+
+
+def key(id):
+    return struct.pack(">Q", id)
+
+
+def png():
+    def chunk(kind, data):
+        return struct.pack(">I", len(data)) + kind + data + struct.pack(">I", zlib.crc32(kind + data))
+
+    rows = b"\x00" + b"\xff\x00\x00" * 2
+    return (
+        b"\x89PNG\r\n\x1a\n"
+        + chunk(b"IHDR", struct.pack(">IIBBBBB", 2, 2, 8, 2, 0, 0, 0))
+        + chunk(b"IDAT", zlib.compress(rows * 2))
+        + chunk(b"IEND", b"")
+    )
+
+
+def write(path, pages, root):
+    high_water = 3 + sum(len(p) // PAGE_SIZE for p in pages)
+    out = b""
+    for page in [meta(0, root, 1, high_water), meta(1, root, 2, high_water), header(2, FREELIST, 0)]:
+        out += page.ljust(PAGE_SIZE, b"\x00")
+    for page in pages:
+        out += page.ljust(-(-len(page) // PAGE_SIZE) * PAGE_SIZE, b"\x00")
+    path.write_bytes(out)
+
+
+def main():
+    here = Path(__file__).parent
+
+    inline = leaf(0, [(0, key(1), b"hello"), (0, key(2), b"foo\nbar")])
+    write(here / "cliphist-inline.db", [leaf(3, [(BUCKET_LEAF, b"b", bucket(0, inline))])], 3)
+
+    root = leaf(
+        3,
+        [
+            (BUCKET_LEAF, b"a", bucket(0, leaf(0, [(0, b"k", b"v")]))),
+            (BUCKET_LEAF, b"b", bucket(4)),
+            (0, b"c", b"not a bucket"),
+        ],
+    )
+    pages = [
+        root,
+        branch(4, [(key(1), 5), (key(4), 6)]),
+        leaf(5, [(0, key(1), b"hello"), (0, key(2), b"https://example.com"), (0, key(3), png())]),
+        leaf(6, [(0, key(4), b"world"), (0, key(5), b"x" * 3000)]),
+    ]
+    write(here / "cliphist-tree.db", pages, 3)
+
+
+if __name__ == "__main__":
+    main()